@@ -0,0 +1,136 @@
+//! Just enough ISO base media (MP4) box handling to rewrite metadata.
+
+use std::io::{self, Error, ErrorKind, Read};
+
+pub type FourCc = [u8; 4];
+
+pub const MOOV: FourCc = *b"moov";
+pub const MDAT: FourCc = *b"mdat";
+pub const FREE: FourCc = *b"free";
+pub const SKIP: FourCc = *b"skip";
+pub const MOOF: FourCc = *b"moof";
+pub const SIDX: FourCc = *b"sidx";
+pub const MVHD: FourCc = *b"mvhd";
+pub const TRAK: FourCc = *b"trak";
+pub const MDIA: FourCc = *b"mdia";
+pub const MINF: FourCc = *b"minf";
+pub const STBL: FourCc = *b"stbl";
+pub const STSD: FourCc = *b"stsd";
+pub const STCO: FourCc = *b"stco";
+pub const CO64: FourCc = *b"co64";
+pub const UDTA: FourCc = *b"udta";
+pub const META: FourCc = *b"meta";
+pub const HDLR: FourCc = *b"hdlr";
+pub const ILST: FourCc = *b"ilst";
+pub const DATA: FourCc = *b"data";
+pub const MEAN: FourCc = *b"mean";
+pub const NAME: FourCc = *b"name";
+pub const FREEFORM: FourCc = *b"----";
+pub const COVR: FourCc = *b"covr";
+
+/// Well-known type indicators for `data` atoms.
+pub const TYPE_IMPLICIT: u32 = 0;
+pub const TYPE_UTF8: u32 = 1;
+pub const TYPE_JPEG: u32 = 13;
+pub const TYPE_PNG: u32 = 14;
+pub const TYPE_INTEGER: u32 = 21;
+
+/// A box header. `size` covers the header too.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: FourCc,
+    pub size: u64,
+    pub header_len: usize,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads a box header. A size of 0 means "to the end", which is `remaining`.
+pub fn read_header<R: Read>(reader: &mut R, remaining: u64) -> io::Result<BoxHeader> {
+    let mut head = [0u8; 8];
+    reader.read_exact(&mut head)?;
+    let kind: FourCc = [head[4], head[5], head[6], head[7]];
+    let size = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as u64;
+    let (size, header_len) = match size {
+        0 => (remaining, 8),
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            (u64::from_be_bytes(large), 16)
+        }
+        size => (size, 8),
+    };
+    if size < header_len as u64 {
+        return Err(invalid("MP4 box smaller than its header"));
+    }
+    Ok(BoxHeader { kind, size, header_len })
+}
+
+/// A child box inside an in-memory payload. `raw` includes the header.
+#[derive(Debug, Clone, Copy)]
+pub struct Child<'a> {
+    pub kind: FourCc,
+    pub data: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+/// Splits a container box's payload into its children.
+pub fn children(payload: &[u8]) -> io::Result<Vec<Child<'_>>> {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos + 8 <= payload.len() {
+        let remaining = (payload.len() - pos) as u64;
+        let header = read_header(&mut &payload[pos..], remaining)?;
+        let end = (header.size as usize).checked_add(pos).filter(|end| *end <= payload.len())
+            .ok_or_else(|| invalid("MP4 box runs past its parent"))?;
+        out.push(Child { kind: header.kind, data: &payload[pos + header.header_len..end], raw: &payload[pos..end] });
+        pos = end;
+    }
+    Ok(out)
+}
+
+pub fn make_box(kind: FourCc, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(&kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+pub fn full_box(kind: FourCc, version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut body = ((version as u32) << 24 | (flags & 0x00FF_FFFF)).to_be_bytes().to_vec();
+    body.extend_from_slice(payload);
+    make_box(kind, &body)
+}
+
+/// Header for a `free` box spanning `total_len` bytes; the body is left as is.
+pub fn free_header(total_len: u64) -> Option<Vec<u8>> {
+    if !(8..=u32::MAX as u64).contains(&total_len) {
+        return None;
+    }
+    let mut out = (total_len as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(&FREE);
+    Some(out)
+}
+
+/// A `data` atom as used inside `ilst` items.
+pub fn data_atom(type_indicator: u32, value: &[u8]) -> Vec<u8> {
+    let mut body = type_indicator.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0, 0, 0, 0]);
+    body.extend_from_slice(value);
+    make_box(DATA, &body)
+}
+
+pub fn fourcc_string(kind: &FourCc) -> String {
+    kind.iter().map(|b| *b as char).collect()
+}
+
+pub fn string_fourcc(name: &str) -> Option<FourCc> {
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() != 4 || chars.iter().any(|c| *c as u32 > 0xFF) {
+        return None;
+    }
+    Some([chars[0] as u8, chars[1] as u8, chars[2] as u8, chars[3] as u8])
+}
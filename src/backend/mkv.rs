@@ -0,0 +1,478 @@
+use super::{ContainerBackend, MediaSummary};
+use crate::cover::{Cover, EmbeddedCover};
+use crate::ebml;
+use crate::job::{Job, Only};
+use crate::mapping::MappingTable;
+use crate::matroska::MatroskaFile;
+use crate::mkvxml;
+use crate::nfo::Nfo;
+use crate::tags::{MergePolicy, Tag, TagSet};
+use log::warn;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Matroska files, edited in place without any external tools.
+pub struct MkvBackend {
+    mapping: MappingTable,
+}
+
+impl Default for MkvBackend {
+    fn default() -> Self {
+        MkvBackend::with_mapping(MappingTable::mkv())
+    }
+}
+
+impl MkvBackend {
+    /// A backend that writes tags using `mapping` instead of the built-in table.
+    pub fn with_mapping(mapping: MappingTable) -> Self {
+        MkvBackend { mapping }
+    }
+
+    fn set_title(&self, mkv: &mut MatroskaFile, title: &str) -> io::Result<()> {
+        let info = mkv.read_element(ebml::INFO)?.unwrap_or_default();
+        let mut payload = Vec::new();
+        for child in ebml::children(&info)?.iter().filter(|c| c.id != ebml::TITLE && c.id != ebml::CRC32) {
+            payload.extend_from_slice(child.raw);
+        }
+        if !title.is_empty() {
+            payload.extend(ebml::string_element(ebml::TITLE, title));
+        }
+        if !mkv.replace_before_clusters(ebml::INFO, &payload)? {
+            warn!("  No room to update the segment title; the TITLE tag is still written");
+        }
+        Ok(())
+    }
+
+    /// Writes `tags` as the file-wide Tag, followed by `kept`, raw Tag
+    /// elements that are carried over unchanged.
+    fn write_tag_elements(&self, mkv: &mut MatroskaFile, tags: &TagSet, kept: &[u8]) -> io::Result<()> {
+        self.set_title(mkv, tags.get("TITLE").unwrap_or_default())?;
+        let mut payload = if tags.is_empty() { Vec::new() } else { tags_payload(tags) };
+        payload.extend_from_slice(kept);
+        if payload.is_empty() {
+            return mkv.replace_element(ebml::TAGS, None);
+        }
+        mkv.replace_element(ebml::TAGS, Some(&payload))
+    }
+
+    /// Splits the existing tags into the flattened file-wide ones, named by
+    /// target level, and the raw Tag elements aimed at a track, edition,
+    /// chapter or attachment.
+    fn split_tags(&self, mkv: &mut MatroskaFile) -> io::Result<(TagSet, Vec<u8>)> {
+        let data = mkv.read_element(ebml::TAGS)?.unwrap_or_default();
+        let mut global = TagSet::new();
+        let mut targeted = Vec::new();
+        let mut seen = Vec::new();
+        for tag in ebml::children(&data)?.iter().filter(|c| c.id == ebml::TAG) {
+            let fields = ebml::children(tag.data)?;
+            if is_aimed(&fields)? {
+                targeted.extend_from_slice(tag.raw);
+                continue;
+            }
+            push_simple_tags(&mut global, next_target(&mut seen, target_level(&fields)?), "", &fields)?;
+        }
+        Ok((global, targeted))
+    }
+
+    fn has_image(&self, mkv: &mut MatroskaFile) -> io::Result<bool> {
+        let attachments = mkv.read_element(ebml::ATTACHMENTS)?.unwrap_or_default();
+        for file in ebml::children(&attachments)?.iter().filter(|c| c.id == ebml::ATTACHED_FILE) {
+            let fields = ebml::children(file.data)?;
+            if fields.iter().any(|f| f.id == ebml::FILE_MIME_TYPE && is_image(&ebml::read_string(f.data))) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Existing attachments, minus jpeg/png images when `drop_images` is set
+    /// and minus images named like `replacing` with any extension, so a new
+    /// cover.jpg replaces an old cover.png.
+    fn kept_attachments(&self, mkv: &mut MatroskaFile, drop_images: bool, replacing: Option<&str>) -> io::Result<Vec<u8>> {
+        let attachments = mkv.read_element(ebml::ATTACHMENTS)?.unwrap_or_default();
+        let mut payload = Vec::new();
+        for file in ebml::children(&attachments)?.iter().filter(|c| c.id == ebml::ATTACHED_FILE) {
+            let fields = ebml::children(file.data)?;
+            let field = |id: u32| fields.iter().find(|f| f.id == id).map(|f| ebml::read_string(f.data)).unwrap_or_default();
+            let image = is_image(&field(ebml::FILE_MIME_TYPE));
+            if image && (drop_images || replacing.is_some_and(|name| stem(name) == stem(&field(ebml::FILE_NAME)))) {
+                continue;
+            }
+            payload.extend_from_slice(file.raw);
+        }
+        Ok(payload)
+    }
+
+    fn write_attachments(&self, mkv: &mut MatroskaFile, payload: Vec<u8>) -> io::Result<()> {
+        if payload.is_empty() {
+            mkv.replace_element(ebml::ATTACHMENTS, None)
+        } else {
+            mkv.replace_element(ebml::ATTACHMENTS, Some(&payload))
+        }
+    }
+}
+
+impl ContainerBackend for MkvBackend {
+    fn name(&self) -> &'static str {
+        "mkv"
+    }
+
+    fn probe(&self, _path: &Path, header: &[u8]) -> bool {
+        header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3])
+            && (header.windows(8).any(|w| w == b"matroska") || header.windows(4).any(|w| w == b"webm"))
+    }
+
+    /// Matroska files are always edited in place; the output folder is ignored.
+    fn target(&self, job: &Job) -> PathBuf {
+        job.video.clone()
+    }
+
+    fn mapping(&self) -> Option<&MappingTable> {
+        Some(&self.mapping)
+    }
+
+    /// Tag names carry their target level, as in `COLLECTION/TITLE`; numeric
+    /// and alternative level names from the mapping are normalized here.
+    /// The further episodes of a multi-episode NFO go under `EPISODE#2/` on.
+    fn tags_for(&self, nfo: &Nfo) -> TagSet {
+        mkvxml::map_tags(&self.mapping, nfo)
+    }
+
+    fn nfo_from_tags(&self, tags: &TagSet) -> Nfo {
+        mkvxml::tags_to_nfo(tags)
+    }
+
+    fn read_tags(&self, path: &Path) -> io::Result<TagSet> {
+        let mut mkv = MatroskaFile::open(path)?;
+        let mut tags = TagSet::new();
+        let Some(data) = mkv.read_element(ebml::TAGS)? else {
+            return Ok(tags);
+        };
+        let mut seen = Vec::new();
+        for tag in ebml::children(&data)?.iter().filter(|c| c.id == ebml::TAG) {
+            let fields = ebml::children(tag.data)?;
+            let level = target_level(&fields)?;
+            let target = if is_aimed(&fields)? { (level, 1) } else { next_target(&mut seen, level) };
+            push_simple_tags(&mut tags, target, "", &fields)?;
+        }
+        Ok(tags)
+    }
+
+    fn summarize(&self, path: &Path) -> io::Result<MediaSummary> {
+        let mut mkv = MatroskaFile::open(path)?;
+        let info = mkv.read_element(ebml::INFO)?.unwrap_or_default();
+        let info = ebml::children(&info)?;
+        let scale = info.iter().find(|c| c.id == ebml::TIMECODE_SCALE).map_or(1_000_000, |c| ebml::read_uint(c.data));
+        let duration = info.iter().find(|c| c.id == ebml::DURATION)
+            .and_then(|c| ebml::read_float(c.data))
+            .map(|ticks| ticks * scale as f64 / 1e9);
+
+        let tracks = mkv.read_element(ebml::TRACKS)?.unwrap_or_default();
+        let mut streams = Vec::new();
+        for entry in ebml::children(&tracks)?.iter().filter(|c| c.id == ebml::TRACK_ENTRY) {
+            let fields = ebml::children(entry.data)?;
+            let kind = match fields.iter().find(|f| f.id == ebml::TRACK_TYPE).map(|f| ebml::read_uint(f.data)) {
+                Some(1) => "video".to_string(),
+                Some(2) => "audio".to_string(),
+                Some(17) => "subtitle".to_string(),
+                other => other.map(|t| t.to_string()).unwrap_or_default(),
+            };
+            let codec = fields.iter().find(|f| f.id == ebml::CODEC_ID).map(|f| ebml::read_string(f.data)).unwrap_or_default();
+            streams.push(format!("{}:{}", kind, codec));
+        }
+        Ok(MediaSummary { streams, duration })
+    }
+
+    /// Prefers the portrait `cover.*` attachment, then any other image.
+    fn read_cover(&self, path: &Path) -> io::Result<Option<EmbeddedCover>> {
+        let mut mkv = MatroskaFile::open(path)?;
+        let attachments = mkv.read_element(ebml::ATTACHMENTS)?.unwrap_or_default();
+        let mut best: Option<(bool, &[u8])> = None;
+        for file in ebml::children(&attachments)?.iter().filter(|c| c.id == ebml::ATTACHED_FILE) {
+            let fields = ebml::children(file.data)?;
+            let field = |id: u32| fields.iter().find(|f| f.id == id).map(|f| f.data);
+            let mime_type = field(ebml::FILE_MIME_TYPE).map(ebml::read_string).unwrap_or_default();
+            let (Some(data), "image/jpeg" | "image/png") = (field(ebml::FILE_DATA), mime_type.as_str()) else {
+                continue;
+            };
+            let portrait = field(ebml::FILE_NAME).map(ebml::read_string).is_some_and(|n| n.starts_with("cover."));
+            if best.is_none() || (portrait && !best.is_some_and(|(p, _)| p)) {
+                best = Some((portrait, data));
+            }
+        }
+        Ok(best.map(|(_, data)| EmbeddedCover::from_bytes(data.to_vec())))
+    }
+
+    fn write_tags(&self, job: &Job, tags: &TagSet) -> io::Result<()> {
+        let mut mkv = MatroskaFile::edit(&job.video)?;
+        self.write_tag_elements(&mut mkv, tags, &[])?;
+        mkv.flush()
+    }
+
+    fn attach_cover(&self, job: &Job, cover: &Cover) -> io::Result<()> {
+        let mut mkv = MatroskaFile::edit(&job.video)?;
+        let name = cover_name(cover);
+        let mut payload = self.kept_attachments(&mut mkv, false, Some(&name))?;
+        payload.extend(attached_file(cover, &name)?);
+        self.write_attachments(&mut mkv, payload)?;
+        mkv.flush()
+    }
+
+    fn strip(&self, job: &Job) -> io::Result<()> {
+        let mut mkv = MatroskaFile::edit(&job.video)?;
+        let payload = self.kept_attachments(&mut mkv, true, None)?;
+        self.write_attachments(&mut mkv, payload)?;
+        mkv.replace_element(ebml::TAGS, None)?;
+        mkv.flush()
+    }
+
+    fn describe(&self, job: &Job, tags: Option<&TagSet>, cover: Option<&Cover>) -> Vec<String> {
+        let mut steps = vec![format!("Edit {} in place", job.video.display())];
+        if let Some(tags) = tags {
+            steps.push(match (job.merge, job.only) {
+                (Some(policy), _) => format!("Merge with the existing file-wide tags ({}); track and chapter tags are kept", policy),
+                (None, Some(Only::Tags)) => "Remove existing tags; attachments are kept".to_string(),
+                (None, _) => "Remove existing tags and image attachments; other attachments are kept".to_string(),
+            });
+            match tags.get("TITLE") {
+                Some(title) => steps.push(format!("Set the segment title to \"{}\"", title)),
+                None => steps.push("Clear the segment title".to_string()),
+            }
+            let levels: Vec<String> = mkvxml::group_by_target(tags).iter()
+                .map(|((level, copy), group)| match copy {
+                    1 => format!("{} at level {}", group.len(), level),
+                    _ => format!("{} at level {} #{}", group.len(), level, copy),
+                })
+                .collect();
+            steps.push(format!("Write a Tags element with {} SimpleTags ({})", tags.len(), levels.join(", ")));
+        }
+        if let Some(cover) = cover {
+            let replaced = if tags.is_some() && job.merge.is_none() { "" } else { ", replacing any image with the same name" };
+            steps.push(format!("Attach {} as {}{}", cover.path.display(), cover_name(cover), replaced));
+        }
+        steps
+    }
+
+    fn apply(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>) -> io::Result<()> {
+        let mut mkv = MatroskaFile::edit(&job.video)?;
+        let mut payload = self.kept_attachments(&mut mkv, true, None)?;
+        if let Some(cover) = cover {
+            payload.extend(attached_file(cover, &cover_name(cover))?);
+        }
+        self.write_attachments(&mut mkv, payload)?;
+        self.write_tag_elements(&mut mkv, tags, &[])?;
+        mkv.flush()
+    }
+
+    /// Only the file-wide tags are merged; tags aimed at a track or chapter,
+    /// such as mkvmerge's statistics, are left exactly as they are.
+    fn merge(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>, policy: MergePolicy) -> io::Result<()> {
+        let mut mkv = MatroskaFile::edit(&job.video)?;
+        if let Some(cover) = cover {
+            if policy.replaces_cover() || !self.has_image(&mut mkv)? {
+                let mut payload = self.kept_attachments(&mut mkv, true, None)?;
+                payload.extend(attached_file(cover, &cover_name(cover))?);
+                self.write_attachments(&mut mkv, payload)?;
+            }
+        }
+        let (existing, targeted) = self.split_tags(&mut mkv)?;
+        let existing = existing.without(self.provenance_tag());
+        // Nested tags go with their parent: ACTOR.CHARACTER is part of ACTOR.
+        let parent = |tag: &Tag| tag.name.split('.').next().unwrap_or_default().to_ascii_lowercase();
+        let merged = TagSet { tags: policy.merge_keyed(existing.tags, tags.tags.clone(), parent) };
+        self.write_tag_elements(&mut mkv, &merged, &targeted)?;
+        mkv.flush()
+    }
+}
+
+fn is_image(mime_type: &str) -> bool {
+    matches!(mime_type, "image/jpeg" | "image/png")
+}
+
+fn stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// Attachment names follow the Matroska cover art convention.
+fn cover_name(cover: &Cover) -> String {
+    let stem = if cover.landscape { "cover_land" } else { "cover" };
+    let ext = if cover.mime_type == "image/png" { "png" } else { "jpg" };
+    format!("{}.{}", stem, ext)
+}
+
+fn attached_file(cover: &Cover, name: &str) -> io::Result<Vec<u8>> {
+    let data = fs::read(&cover.path)?;
+    let uid = RandomState::new().build_hasher().finish() | 1;
+    let mut payload = ebml::string_element(ebml::FILE_NAME, name);
+    payload.extend(ebml::string_element(ebml::FILE_MIME_TYPE, cover.mime_type));
+    payload.extend(ebml::element(ebml::FILE_DATA, &data));
+    payload.extend(ebml::uint_element(ebml::FILE_UID, uid));
+    Ok(ebml::element(ebml::ATTACHED_FILE, &payload))
+}
+
+/// Flattens the SimpleTags among `fields` into `tags`, naming nested ones
+/// `PARENT.CHILD` after the tag they are in.
+fn push_simple_tags(tags: &mut TagSet, target: mkvxml::Target, parent: &str, fields: &[ebml::Child]) -> io::Result<()> {
+    for simple in fields.iter().filter(|c| c.id == ebml::SIMPLE_TAG) {
+        let fields = ebml::children(simple.data)?;
+        let Some(name) = fields.iter().find(|f| f.id == ebml::TAG_NAME).map(|f| ebml::read_string(f.data)) else {
+            continue;
+        };
+        let name = if parent.is_empty() { name } else { format!("{}.{}", parent, name) };
+        if let Some(value) = fields.iter().find(|f| f.id == ebml::TAG_STRING) {
+            tags.push(&mkvxml::target_name(target, &name), &ebml::read_string(value.data));
+        }
+        push_simple_tags(tags, target, &name, &fields)?;
+    }
+    Ok(())
+}
+
+fn simple_tag_payload(tag: &mkvxml::SimpleTag) -> Vec<u8> {
+    let mut simple = ebml::string_element(ebml::TAG_NAME, &tag.name);
+    if !tag.value.is_empty() {
+        simple.extend(ebml::string_element(ebml::TAG_STRING, &tag.value));
+    }
+    for child in &tag.children {
+        simple.extend(simple_tag_payload(child));
+    }
+    ebml::element(ebml::SIMPLE_TAG, &simple)
+}
+
+/// Whether a Tag's Targets aim it at a track, edition, chapter or attachment
+/// rather than the whole file.
+fn is_aimed(fields: &[ebml::Child]) -> io::Result<bool> {
+    match fields.iter().find(|f| f.id == ebml::TARGETS) {
+        Some(targets) => Ok(ebml::children(targets.data)?.iter().any(|t| matches!(t.id,
+            ebml::TAG_TRACK_UID | ebml::TAG_EDITION_UID | ebml::TAG_CHAPTER_UID | ebml::TAG_ATTACHMENT_UID))),
+        None => Ok(false),
+    }
+}
+
+/// Numbers file-wide Tags that share a level in the order they come, so the
+/// second level 50 Tag of a multi-episode file reads back as `EPISODE#2/`.
+fn next_target(seen: &mut Vec<u64>, level: u64) -> mkvxml::Target {
+    seen.push(level);
+    (level, seen.iter().filter(|l| **l == level).count())
+}
+
+/// The TargetTypeValue of a Tag, 50 when its Targets leave it out.
+fn target_level(fields: &[ebml::Child]) -> io::Result<u64> {
+    let Some(targets) = fields.iter().find(|f| f.id == ebml::TARGETS) else {
+        return Ok(mkvxml::DEFAULT_LEVEL);
+    };
+    Ok(ebml::children(targets.data)?.iter()
+        .find(|t| t.id == ebml::TARGET_TYPE_VALUE)
+        .map_or(mkvxml::DEFAULT_LEVEL, |t| ebml::read_uint(t.data)))
+}
+
+/// One Tag per target level, highest first, each with a TargetTypeValue and
+/// TargetType so players can tell the show or collection from the episode.
+fn tags_payload(tags: &TagSet) -> Vec<u8> {
+    let groups = mkvxml::group_by_target(tags);
+    let episode = mkvxml::has_episodes(tags);
+    let mut payload = Vec::new();
+    for ((level, _), group) in &groups {
+        let mut targets = ebml::uint_element(ebml::TARGET_TYPE_VALUE, *level);
+        if let Some(target_type) = mkvxml::target_type(*level, episode) {
+            targets.extend(ebml::string_element(ebml::TARGET_TYPE, target_type));
+        }
+        let mut tag = ebml::element(ebml::TARGETS, &targets);
+        for simple in mkvxml::nest(group) {
+            tag.extend(simple_tag_payload(&simple));
+        }
+        payload.extend(ebml::element(ebml::TAG, &tag));
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::JobOptions;
+    use tempfile::TempDir;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nfirst";
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0second";
+
+    /// A folder with a small video and two covers, and a job for the video.
+    fn fixture(merge: Option<MergePolicy>) -> (TempDir, Job) {
+        let dir = tempfile::tempdir().unwrap();
+        let track = [ebml::uint_element(ebml::TRACK_TYPE, 1), ebml::string_element(ebml::CODEC_ID, "V_TEST")].concat();
+        let body = [
+            ebml::element(ebml::INFO, &ebml::uint_element(ebml::TIMECODE_SCALE, 1_000_000)),
+            ebml::element(ebml::TRACKS, &ebml::element(ebml::TRACK_ENTRY, &track)),
+            ebml::element(ebml::CLUSTER, &[0x42; 32]),
+        ].concat();
+        let mut bytes = ebml::element(ebml::EBML_HEADER, &ebml::string_element(ebml::DOC_TYPE, "matroska"));
+        bytes.extend(ebml::encode_id(ebml::SEGMENT));
+        bytes.extend(ebml::encode_size_width(body.len() as u64, 8).unwrap());
+        bytes.extend(body);
+        let video = dir.path().join("movie.mkv");
+        fs::write(&video, bytes).unwrap();
+        fs::write(dir.path().join("first.png"), PNG).unwrap();
+        fs::write(dir.path().join("second.png"), JPEG).unwrap();
+        let job = Job::resolve(&video, None, None, &JobOptions { merge, ..JobOptions::default() });
+        (dir, job)
+    }
+
+    fn cover(dir: &TempDir, name: &str) -> Cover {
+        Cover { path: dir.path().join(name), mime_type: "image/png", landscape: false }
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> TagSet {
+        let mut tags = TagSet::new();
+        for (name, value) in pairs {
+            tags.push(name, value);
+        }
+        tags
+    }
+
+    /// Tags the fixture with a TITLE, two ACTORs and the first cover, then
+    /// merges in `nfo` and the second cover.
+    fn merged(policy: MergePolicy, nfo: &TagSet) -> (TagSet, Vec<u8>) {
+        let backend = MkvBackend::default();
+        let (dir, job) = fixture(Some(policy));
+        let existing = tags(&[("TITLE", "Old"), ("ACTOR", "A"), ("ACTOR.CHARACTER", "Hero"), ("ACTOR", "B"), ("NFO2TAGS", "stamp")]);
+        backend.apply(&job, &existing, Some(&cover(&dir, "first.png"))).unwrap();
+        backend.merge(&job, nfo, Some(&cover(&dir, "second.png")), policy).unwrap();
+        let embedded = backend.read_cover(&job.video).unwrap().unwrap();
+        (backend.read_tags(&job.video).unwrap(), embedded.data)
+    }
+
+    #[test]
+    fn nfo_wins_replaces_names_and_the_cover() {
+        let (written, image) = merged(MergePolicy::NfoWins, &tags(&[("TITLE", "New"), ("GENRE", "Crime")]));
+        assert_eq!(written.get_all("TITLE"), ["New"]);
+        assert_eq!(written.get_all("ACTOR"), ["A", "B"]);
+        assert_eq!(written.get("ACTOR.CHARACTER"), Some("Hero"));
+        assert_eq!(written.get("GENRE"), Some("Crime"));
+        assert_eq!(written.get("NFO2TAGS"), None);
+        assert_eq!(image, JPEG);
+    }
+
+    #[test]
+    fn existing_wins_keeps_names_but_replaces_the_cover() {
+        let (written, image) = merged(MergePolicy::ExistingWins, &tags(&[("TITLE", "New"), ("ACTOR", "C"), ("GENRE", "Crime")]));
+        assert_eq!(written.get_all("TITLE"), ["Old"]);
+        assert_eq!(written.get_all("ACTOR"), ["A", "B"]);
+        assert_eq!(written.get("GENRE"), Some("Crime"));
+        assert_eq!(image, JPEG);
+    }
+
+    #[test]
+    fn fill_missing_keeps_the_cover() {
+        let (written, image) = merged(MergePolicy::FillMissing, &tags(&[("TITLE", "New"), ("GENRE", "Crime")]));
+        assert_eq!(written.get_all("TITLE"), ["Old"]);
+        assert_eq!(written.get("GENRE"), Some("Crime"));
+        assert_eq!(image, PNG);
+
+        // Without a cover in the file the new one goes in.
+        let backend = MkvBackend::default();
+        let (dir, job) = fixture(Some(MergePolicy::FillMissing));
+        backend.merge(&job, &tags(&[("TITLE", "New")]), Some(&cover(&dir, "second.png")), MergePolicy::FillMissing).unwrap();
+        assert_eq!(backend.read_cover(&job.video).unwrap().unwrap().data, JPEG);
+    }
+}
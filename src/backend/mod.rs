@@ -0,0 +1,186 @@
+pub mod mkv;
+pub mod mp4;
+
+pub use mkv::MkvBackend;
+pub use mp4::Mp4Backend;
+
+use crate::cover::{Cover, EmbeddedCover};
+use crate::job::{disc_root, Job, Only};
+use crate::mapping::MappingTable;
+use crate::nfo::Nfo;
+use crate::tags::{MergePolicy, TagSet};
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+/// How many leading bytes are handed to [`ContainerBackend::probe`].
+pub const SNIFF_LEN: usize = 64;
+
+/// The parts of a file that tagging must never change, used to check a
+/// result against its source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaSummary {
+    /// One entry per track, as `kind:codec`.
+    pub streams: Vec<String>,
+    /// Length in seconds, if the container records it.
+    pub duration: Option<f64>,
+}
+
+/// One video container format. Implementations decide how NFO data maps onto
+/// their tags and how those tags, and the cover, get into the file.
+pub trait ContainerBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns true if this backend understands the file. `header` holds up to
+    /// [`SNIFF_LEN`] bytes from the start of the file.
+    fn probe(&self, path: &Path, header: &[u8]) -> bool;
+
+    /// The file [`ContainerBackend::apply`] writes to: `job.output` unless the
+    /// backend always edits the video in place.
+    fn target(&self, job: &Job) -> PathBuf {
+        job.output.clone()
+    }
+
+    /// The tag holding the [`crate::provenance`] stamp.
+    fn provenance_tag(&self) -> &'static str {
+        "NFO2TAGS"
+    }
+
+    /// The table [`ContainerBackend::tags_for`] maps with, if it uses one.
+    fn mapping(&self) -> Option<&MappingTable> {
+        None
+    }
+
+    /// Maps an NFO onto this container's tag names.
+    fn tags_for(&self, nfo: &Nfo) -> TagSet;
+
+    /// The inverse of [`ContainerBackend::tags_for`], used to rebuild an NFO
+    /// from a file's embedded tags.
+    fn nfo_from_tags(&self, tags: &TagSet) -> Nfo;
+
+    /// Reads the tags currently embedded in the file.
+    fn read_tags(&self, path: &Path) -> io::Result<TagSet>;
+
+    /// Lists the tracks and duration. Backends that can't tell return an empty
+    /// summary, which skips those checks during verification.
+    fn summarize(&self, _path: &Path) -> io::Result<MediaSummary> {
+        Ok(MediaSummary::default())
+    }
+
+    /// Reads the embedded cover art, if there is any.
+    fn read_cover(&self, _path: &Path) -> io::Result<Option<EmbeddedCover>> {
+        Ok(None)
+    }
+
+    /// Replaces the tags in the file, leaving cover art alone.
+    fn write_tags(&self, job: &Job, tags: &TagSet) -> io::Result<()>;
+
+    /// Embeds `cover` in place of the current cover art, leaving tags alone.
+    fn attach_cover(&self, job: &Job, cover: &Cover) -> io::Result<()>;
+
+    /// Removes existing tags and embedded cover art.
+    fn strip(&self, job: &Job) -> io::Result<()>;
+
+    /// Describes what [`ContainerBackend::apply`] or [`ContainerBackend::merge`]
+    /// would do, for dry runs. `tags` is None when the tags are left alone.
+    fn describe(&self, job: &Job, tags: Option<&TagSet>, cover: Option<&Cover>) -> Vec<String> {
+        let target = self.target(job);
+        let mut steps = Vec::new();
+        match tags {
+            Some(tags) => {
+                steps.push(match (job.merge, job.only) {
+                    (Some(policy), _) => format!("Merge into the existing tags of {} ({})", target.display(), policy),
+                    (None, Some(Only::Tags)) => format!("Remove existing tags from {}, keeping cover art", target.display()),
+                    (None, _) => format!("Remove existing tags and cover art from {}", target.display()),
+                });
+                steps.push(format!("Write {} tags", tags.len()));
+            }
+            None => steps.push(format!("Leave the tags in {} alone", target.display())),
+        }
+        if let Some(cover) = cover {
+            steps.push(format!("Embed cover {}", cover.path.display()));
+        }
+        steps
+    }
+
+    /// Strips the file, then writes `tags` and the optional cover. Backends that
+    /// can do this in one pass should override it.
+    fn apply(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>) -> io::Result<()> {
+        self.strip(job)?;
+        self.write_tags(job, tags)?;
+        if let Some(cover) = cover {
+            self.attach_cover(job, cover)?;
+        }
+        Ok(())
+    }
+
+    /// Combines `tags` with the ones already in the file according to `policy`
+    /// and writes the result. An old provenance stamp is always replaced. The
+    /// cover is written unless the file has one and the policy keeps it.
+    fn merge(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>, policy: MergePolicy) -> io::Result<()> {
+        let existing = self.read_tags(&job.video)?.without(self.provenance_tag());
+        let has_cover = self.read_cover(&job.video)?.is_some();
+        self.write_tags(job, &policy.merge(&existing, tags))?;
+        if let Some(cover) = cover.filter(|_| policy.replaces_cover() || !has_cover) {
+            self.attach_cover(job, cover)?;
+        }
+        Ok(())
+    }
+}
+
+/// The backends available to a run, tried in order when sniffing a file.
+pub struct Registry {
+    backends: Vec<Box<dyn ContainerBackend>>,
+}
+
+impl Registry {
+    /// A registry with no backends.
+    pub fn empty() -> Self {
+        Registry { backends: Vec::new() }
+    }
+
+    /// Adds a backend. Backends registered later are tried first, so a library
+    /// user can take over a format from the built-in MP4 or MKV backend.
+    pub fn register(&mut self, backend: Box<dyn ContainerBackend>) {
+        self.backends.insert(0, backend);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ContainerBackend> {
+        self.backends.iter().find(|b| b.name() == name).map(|b| b.as_ref())
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &dyn ContainerBackend> {
+        self.backends.iter().map(|b| b.as_ref())
+    }
+
+    /// Picks the backend for a file from its leading bytes, not its extension.
+    /// DVD and Blu-ray folders, and the files in them, are reported as such.
+    pub fn detect(&self, path: &Path) -> io::Result<&dyn ContainerBackend> {
+        let backend = if path.is_dir() {
+            None
+        } else {
+            let mut header = Vec::with_capacity(SNIFF_LEN);
+            File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+            self.backends().find(|b| b.probe(path, &header))
+        };
+        backend.ok_or_else(|| match disc_root(path) {
+            Some((disc, _)) => Error::new(
+                ErrorKind::Unsupported,
+                format!("{} is part of a {} folder structure, which can't be tagged. Remux it to MKV to tag it.", path.display(), disc.name())
+            ),
+            None => Error::new(
+                ErrorKind::Unsupported,
+                format!("{} is not a supported container. It only works with MP4 and MKV files.", path.display())
+            ),
+        })
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register(Box::new(Mp4Backend::default()));
+        registry.register(Box::new(MkvBackend::default()));
+        registry
+    }
+}
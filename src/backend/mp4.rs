@@ -0,0 +1,654 @@
+use super::{ContainerBackend, MediaSummary};
+use crate::atom::{self, FourCc};
+use crate::cover::{Cover, EmbeddedCover};
+use crate::isobmff::Mp4File;
+use crate::job::{Job, Only};
+use crate::mapping::MappingTable;
+use crate::mkvxml::split_list;
+use crate::nfo::{Nfo, NfoKind, Person, UniqueId};
+use crate::tags::{MergePolicy, TagSet};
+use log::warn;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// MP4 files, edited in place by rewriting the iTunes metadata list.
+pub struct Mp4Backend {
+    mapping: MappingTable,
+}
+
+impl Default for Mp4Backend {
+    fn default() -> Self {
+        Mp4Backend::with_mapping(MappingTable::mp4())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Value {
+    Text,
+    /// Big-endian integer of the given width in bytes.
+    Integer(usize),
+    /// An integer that stands for a name. Tags use the first name; the others
+    /// and the number itself are accepted too.
+    Named(usize, &'static [(u64, &'static [&'static str])]),
+}
+
+/// `stik` values.
+const MEDIA_TYPES: &[(u64, &[&str])] = &[
+    (0, &["Home Video"]),
+    (1, &["Music"]),
+    (2, &["Audiobook"]),
+    (6, &["Music Video"]),
+    (9, &["Movie"]),
+    (10, &["TV Show", "Episode"]),
+    (11, &["Booklet"]),
+    (14, &["Ringtone"]),
+    (21, &["Podcast"]),
+];
+
+/// `hdvd` values.
+const HD_VIDEO: &[(u64, &[&str])] = &[
+    (0, &["SD"]),
+    (1, &["720p"]),
+    (2, &["1080p"]),
+    (3, &["2160p", "4K"]),
+];
+
+/// Tag names (as ffmpeg calls them) and the `ilst` atoms they live in.
+const ATOMS: &[(&str, &[u8; 4], Value)] = &[
+    ("title", b"\xA9nam", Value::Text),
+    ("artist", b"\xA9ART", Value::Text),
+    ("album_artist", b"aART", Value::Text),
+    ("album", b"\xA9alb", Value::Text),
+    ("genre", b"\xA9gen", Value::Text),
+    ("date", b"\xA9day", Value::Text),
+    ("comment", b"\xA9cmt", Value::Text),
+    ("composer", b"\xA9wrt", Value::Text),
+    ("encoder", b"\xA9too", Value::Text),
+    ("grouping", b"\xA9grp", Value::Text),
+    ("lyrics", b"\xA9lyr", Value::Text),
+    ("copyright", b"cprt", Value::Text),
+    ("keywords", b"keyw", Value::Text),
+    ("description", b"desc", Value::Text),
+    ("synopsis", b"ldes", Value::Text),
+    ("show", b"tvsh", Value::Text),
+    ("episode_id", b"tven", Value::Text),
+    ("network", b"tvnn", Value::Text),
+    ("sort_name", b"sonm", Value::Text),
+    ("sort_artist", b"soar", Value::Text),
+    ("sort_album_artist", b"soaa", Value::Text),
+    ("sort_album", b"soal", Value::Text),
+    ("sort_composer", b"soco", Value::Text),
+    ("sort_show", b"sosn", Value::Text),
+    ("season_number", b"tvsn", Value::Integer(4)),
+    ("episode_sort", b"tves", Value::Integer(4)),
+    ("media_type", b"stik", Value::Named(1, MEDIA_TYPES)),
+    ("hd_video", b"hdvd", Value::Named(1, HD_VIDEO)),
+    ("gapless_playback", b"pgap", Value::Integer(1)),
+    ("compilation", b"cpil", Value::Integer(1)),
+];
+
+impl Mp4Backend {
+    /// A backend that writes tags using `mapping` instead of the built-in
+    /// table. Tag names are the ones in `ATOMS`, four-character atom names or
+    /// `----:mean:name` freeform items.
+    pub fn with_mapping(mapping: MappingTable) -> Self {
+        Mp4Backend { mapping }
+    }
+
+    /// The file to edit. With a separate output path the video is copied there
+    /// first, unless a copy is already there and `fresh` is false. Runs with
+    /// `--only` update an existing copy, so the half they don't touch survives.
+    fn working_file(&self, job: &Job, fresh: bool) -> io::Result<PathBuf> {
+        if job.output == job.video {
+            return Ok(job.video.clone());
+        }
+        if fresh || !job.output.exists() {
+            fs::copy(&job.video, &job.output)?;
+            make_writable(&job.output)?;
+        }
+        Ok(job.output.clone())
+    }
+
+    /// Raw `ilst` items currently in the file.
+    fn items(&self, mp4: &mut Mp4File) -> io::Result<Vec<(FourCc, Vec<u8>)>> {
+        let Some(ilst) = mp4.read_ilst()? else {
+            return Ok(Vec::new());
+        };
+        Ok(atom::children(&ilst)?.iter().map(|c| (c.kind, c.raw.to_vec())).collect())
+    }
+
+    fn write_items(&self, mp4: &mut Mp4File, items: &[Vec<u8>]) -> io::Result<()> {
+        if items.is_empty() {
+            mp4.replace_ilst(None)?;
+        } else {
+            mp4.replace_ilst(Some(&items.concat()))?;
+        }
+        mp4.flush()
+    }
+}
+
+impl ContainerBackend for Mp4Backend {
+    fn name(&self) -> &'static str {
+        "mp4"
+    }
+
+    fn probe(&self, _path: &Path, header: &[u8]) -> bool {
+        header.len() >= 8 && &header[4..8] == b"ftyp"
+    }
+
+    fn provenance_tag(&self) -> &'static str {
+        "----:com.nfo2tags:source"
+    }
+
+    fn mapping(&self) -> Option<&MappingTable> {
+        Some(&self.mapping)
+    }
+
+    /// Values of named integers like `media_type` are given by name, so
+    /// `9` and `Movie` both come out as `Movie`. Values the atom can't hold,
+    /// like text for a number, are dropped here so the plan and the check
+    /// after writing only see what is actually written.
+    fn tags_for(&self, nfo: &Nfo) -> TagSet {
+        let mut tags = TagSet::new();
+        for tag in self.mapping.apply(nfo).iter() {
+            if let Err(problem) = tag_item(&tag.name, &tag.value) {
+                warn!("  Not writing a tag: {}", problem);
+                continue;
+            }
+            let value = match ATOMS.iter().find(|(name, _, _)| *name == tag.name) {
+                Some((_, _, Value::Named(_, names))) => lookup(names, &tag.value)
+                    .and_then(|number| name_of(names, number))
+                    .unwrap_or(&tag.value),
+                _ => &tag.value,
+            };
+            tags.push(&tag.name, value);
+        }
+        tags
+    }
+
+    fn nfo_from_tags(&self, tags: &TagSet) -> Nfo {
+        let text = |name: &str| tags.get(name).unwrap_or_default().to_string();
+        let number = |name: &str| tags.get(name).and_then(|v| v.trim().parse().ok());
+        let list = |name: &str| tags.get(name).map(split_list).unwrap_or_default();
+        // The plot goes in both descriptions. Older versions put the outline
+        // in the long one, so the longer text is taken as the plot.
+        let (mut plot, mut outline) = (text("description"), text("synopsis"));
+        if plot.len() < outline.len() {
+            std::mem::swap(&mut plot, &mut outline);
+        }
+        if outline == plot {
+            outline.clear();
+        }
+        let mut nfo = Nfo {
+            title: text("title"),
+            sorttitle: text("sort_name"),
+            genres: list("genre"),
+            tags: list("keywords"),
+            plot,
+            outline,
+            showtitle: text("show"),
+            season: number("season_number"),
+            episode: number("episode_sort").or_else(|| number("episode_id")),
+            studios: list("network"),
+            mpaa: tags.get("----:com.apple.iTunes:iTunEXTC")
+                .and_then(|rating| rating.split('|').nth(1))
+                .unwrap_or_default()
+                .to_string(),
+            ..Nfo::default()
+        };
+        nfo.actors = list("artist").into_iter().enumerate()
+            .map(|(order, name)| Person { name, order: Some(order as u32), ..Person::default() })
+            .collect();
+        for id_type in ["imdb", "tmdb", "tvdb"] {
+            if let Some(value) = tags.get(&format!("----:com.apple.iTunes:{}", id_type.to_ascii_uppercase())) {
+                let default = nfo.unique_ids.is_empty();
+                nfo.unique_ids.push(UniqueId { id_type: id_type.to_string(), default, value: value.to_string() });
+            }
+        }
+        let date = text("date");
+        nfo.year = date.get(..4).and_then(|y| y.parse().ok());
+        if nfo.is_tv_episode() || tags.get("media_type") == Some("TV Show") {
+            nfo.kind = NfoKind::Episode;
+            nfo.aired = date;
+        } else {
+            nfo.premiered = date;
+        }
+        nfo
+    }
+
+    fn read_tags(&self, path: &Path) -> io::Result<TagSet> {
+        let mut mp4 = Mp4File::open(path)?;
+        let mut tags = TagSet::new();
+        let Some(ilst) = mp4.read_ilst()? else {
+            return Ok(tags);
+        };
+        for item in atom::children(&ilst)? {
+            if item.kind == atom::COVR {
+                continue;
+            }
+            let fields = atom::children(item.data)?;
+            let known = ATOMS.iter().find(|(_, kind, _)| **kind == item.kind);
+            let name = if item.kind == atom::FREEFORM {
+                let text = |kind: FourCc| fields.iter().find(|f| f.kind == kind)
+                    .map(|f| String::from_utf8_lossy(f.data.get(4..).unwrap_or_default()).into_owned())
+                    .unwrap_or_default();
+                format!("----:{}:{}", text(atom::MEAN), text(atom::NAME))
+            } else {
+                known.map(|(name, _, _)| name.to_string())
+                    .unwrap_or_else(|| atom::fourcc_string(&item.kind))
+            };
+            for data in fields.iter().filter(|f| f.kind == atom::DATA && f.data.len() >= 8) {
+                if let Some(value) = decode_value(&item.kind, data.data) {
+                    let named = match known {
+                        Some((_, _, Value::Named(_, names))) => value.parse().ok().and_then(|n| name_of(names, n)),
+                        _ => None,
+                    };
+                    tags.push(&name, named.unwrap_or(&value));
+                }
+            }
+        }
+        Ok(tags)
+    }
+
+    fn summarize(&self, path: &Path) -> io::Result<MediaSummary> {
+        let moov = Mp4File::open(path)?.read_moov()?;
+        let mut summary = MediaSummary::default();
+        for child in atom::children(&moov)? {
+            if child.kind == atom::MVHD {
+                summary.duration = movie_duration(child.data);
+            } else if child.kind == atom::TRAK {
+                summary.streams.push(track_label(child.data)?);
+            }
+        }
+        Ok(summary)
+    }
+
+    fn read_cover(&self, path: &Path) -> io::Result<Option<EmbeddedCover>> {
+        let mut mp4 = Mp4File::open(path)?;
+        for (kind, raw) in self.items(&mut mp4)? {
+            if kind != atom::COVR {
+                continue;
+            }
+            let image = atom::children(&raw[8..])?.into_iter()
+                .find(|c| c.kind == atom::DATA && c.data.len() > 8)
+                .map(|c| EmbeddedCover::from_bytes(c.data[8..].to_vec()));
+            return Ok(image);
+        }
+        Ok(None)
+    }
+
+    fn write_tags(&self, job: &Job, tags: &TagSet) -> io::Result<()> {
+        let mut mp4 = Mp4File::edit(&self.working_file(job, false)?)?;
+        let mut items: Vec<Vec<u8>> = self.items(&mut mp4)?.into_iter()
+            .filter(|(kind, _)| *kind == atom::COVR)
+            .map(|(_, raw)| raw)
+            .collect();
+        items.splice(0..0, tag_items(tags));
+        self.write_items(&mut mp4, &items)
+    }
+
+    fn attach_cover(&self, job: &Job, cover: &Cover) -> io::Result<()> {
+        let mut mp4 = Mp4File::edit(&self.working_file(job, false)?)?;
+        let mut items: Vec<Vec<u8>> = self.items(&mut mp4)?.into_iter()
+            .filter(|(kind, _)| *kind != atom::COVR)
+            .map(|(_, raw)| raw)
+            .collect();
+        items.push(cover_item(cover)?);
+        self.write_items(&mut mp4, &items)
+    }
+
+    fn strip(&self, job: &Job) -> io::Result<()> {
+        let mut mp4 = Mp4File::edit(&self.working_file(job, false)?)?;
+        self.write_items(&mut mp4, &[])
+    }
+
+    fn describe(&self, job: &Job, tags: Option<&TagSet>, cover: Option<&Cover>) -> Vec<String> {
+        let mut steps = Vec::new();
+        if job.output != job.video && (job.only.is_none() || !job.output.exists()) {
+            steps.push(format!("Copy {} to {}", job.video.display(), job.output.display()));
+        }
+        steps.push(format!("Edit {} in place", job.output.display()));
+        if let Some(tags) = tags {
+            steps.push(match (job.merge, job.only) {
+                (Some(policy), _) => format!("Merge {} items into the ilst metadata list ({})", tags.len(), policy),
+                (None, Some(Only::Tags)) => format!("Replace the ilst metadata list with {} items, keeping the covr image", tags.len()),
+                (None, _) => format!("Replace the ilst metadata list with {} items", tags.len()),
+            });
+        }
+        if let Some(cover) = cover {
+            steps.push(format!("Replace the covr image with {}", cover.path.display()));
+        }
+        if job.delete_original && job.output != job.video {
+            steps.push(format!("Delete {}", job.video.display()));
+        }
+        steps
+    }
+
+    fn apply(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>) -> io::Result<()> {
+        let mut mp4 = Mp4File::edit(&self.working_file(job, true)?)?;
+        let mut items = tag_items(tags);
+        if let Some(cover) = cover {
+            items.push(cover_item(cover)?);
+        }
+        self.write_items(&mut mp4, &items)
+    }
+
+    /// Merges whole `ilst` items, so atoms nfo2tags doesn't know about are
+    /// kept byte for byte.
+    fn merge(&self, job: &Job, tags: &TagSet, cover: Option<&Cover>, policy: MergePolicy) -> io::Result<()> {
+        let mut mp4 = Mp4File::edit(&self.working_file(job, job.only.is_none())?)?;
+        let (covers, existing): (Vec<_>, Vec<_>) = self.items(&mut mp4)?.into_iter()
+            .map(|(_, raw)| raw)
+            .filter(|raw| item_key(raw) != self.provenance_tag().as_bytes())
+            .partition(|raw| raw[4..8] == atom::COVR);
+        let mut items = policy.merge_keyed(existing, tag_items(tags), |raw| item_key(raw));
+        match cover {
+            Some(cover) if policy.replaces_cover() || covers.is_empty() => items.push(cover_item(cover)?),
+            _ => items.extend(covers),
+        }
+        self.write_items(&mut mp4, &items)
+    }
+}
+
+/// The copy keeps the original's permissions, which may be read-only.
+fn make_writable(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    if !permissions.readonly() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+/// Seconds from an `mvhd` payload.
+fn movie_duration(mvhd: &[u8]) -> Option<f64> {
+    let field = |at: usize, width: usize| mvhd.get(at..at + width).map(|b| b.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64));
+    let (timescale, duration) = match mvhd.first()? {
+        1 => (field(20, 4)?, field(24, 8)?),
+        _ => (field(12, 4)?, field(16, 4)?),
+    };
+    if timescale == 0 { None } else { Some(duration as f64 / timescale as f64) }
+}
+
+/// `handler:codec` for a `trak`, e.g. `vide:avc1`.
+fn track_label(trak: &[u8]) -> io::Result<String> {
+    let find = |data: &[u8], kind: FourCc| -> io::Result<Vec<u8>> {
+        Ok(atom::children(data)?.into_iter().find(|c| c.kind == kind).map(|c| c.data.to_vec()).unwrap_or_default())
+    };
+    let mdia = find(trak, atom::MDIA)?;
+    let handler = find(&mdia, atom::HDLR)?;
+    let stsd = find(&find(&find(&mdia, atom::MINF)?, atom::STBL)?, atom::STSD)?;
+    let text = |bytes: Option<&[u8]>| bytes.map(|b| String::from_utf8_lossy(b).into_owned()).unwrap_or_default();
+    Ok(format!("{}:{}", text(handler.get(8..12)), text(stsd.get(12..16))))
+}
+
+/// Identifies an `ilst` item: its atom type, plus mean and name for freeform ones.
+fn item_key(raw: &[u8]) -> Vec<u8> {
+    let mut key = raw[4..8].to_vec();
+    if raw[4..8] == atom::FREEFORM {
+        for field in atom::children(&raw[8..]).unwrap_or_default() {
+            if field.kind == atom::MEAN || field.kind == atom::NAME {
+                key.push(b':');
+                key.extend_from_slice(field.data.get(4..).unwrap_or_default());
+            }
+        }
+    }
+    key
+}
+
+/// Turns tags into `ilst` items, leaving out any [`tag_item`] can't encode.
+fn tag_items(tags: &TagSet) -> Vec<Vec<u8>> {
+    tags.iter().filter_map(|tag| tag_item(&tag.name, &tag.value)
+        .inspect_err(|problem| warn!("  Skipping a tag: {}", problem))
+        .ok()).collect()
+}
+
+/// Encodes one tag as an `ilst` item. Unknown four-character names are used
+/// as the atom itself and `----:mean:name` becomes a freeform item.
+fn tag_item(name: &str, value: &str) -> Result<Vec<u8>, String> {
+    if let Some(rest) = name.strip_prefix("----:") {
+        let Some((mean, key)) = rest.split_once(':') else {
+            return Err(format!("freeform tag {} has no name", name));
+        };
+        let mut payload = atom::full_box(atom::MEAN, 0, 0, mean.as_bytes());
+        payload.extend(atom::full_box(atom::NAME, 0, 0, key.as_bytes()));
+        payload.extend(atom::data_atom(atom::TYPE_UTF8, value.as_bytes()));
+        return Ok(atom::make_box(atom::FREEFORM, &payload));
+    }
+
+    let (kind, encoding) = match ATOMS.iter().find(|(n, _, _)| *n == name) {
+        Some((_, kind, encoding)) => (**kind, *encoding),
+        None => match atom::string_fourcc(name) {
+            Some(kind) => (kind, Value::Text),
+            None => return Err(format!("MP4 has no atom for the {} tag", name)),
+        },
+    };
+    let data = match encoding {
+        Value::Text => atom::data_atom(atom::TYPE_UTF8, value.as_bytes()),
+        Value::Integer(width) => match value.trim().parse::<u64>() {
+            Ok(number) => atom::data_atom(atom::TYPE_INTEGER, &number.to_be_bytes()[8 - width..]),
+            Err(_) => return Err(format!("{} must be a number, not {}", name, value)),
+        },
+        Value::Named(width, names) => match lookup(names, value) {
+            Some(number) => atom::data_atom(atom::TYPE_INTEGER, &number.to_be_bytes()[8 - width..]),
+            None => {
+                let known: Vec<&str> = names.iter().map(|(_, n)| n[0]).collect();
+                return Err(format!("{} must be one of {} or a number, not {}", name, known.join(", "), value));
+            }
+        },
+    };
+    Ok(atom::make_box(kind, &data))
+}
+
+/// The number for a named integer, by any of its names or as a number.
+fn lookup(names: &[(u64, &[&str])], value: &str) -> Option<u64> {
+    let value = value.trim();
+    value.parse().ok().or_else(|| names.iter()
+        .find(|(_, n)| n.iter().any(|n| n.eq_ignore_ascii_case(value)))
+        .map(|(number, _)| *number))
+}
+
+fn name_of(names: &[(u64, &'static [&'static str])], number: u64) -> Option<&'static str> {
+    names.iter().find(|(n, _)| *n == number).map(|(_, n)| n[0])
+}
+
+fn cover_item(cover: &Cover) -> io::Result<Vec<u8>> {
+    let image = fs::read(&cover.path)?;
+    let type_indicator = if cover.mime_type == "image/png" { atom::TYPE_PNG } else { atom::TYPE_JPEG };
+    Ok(atom::make_box(atom::COVR, &atom::data_atom(type_indicator, &image)))
+}
+
+/// Renders a `data` atom's value as text. Image data yields nothing.
+fn decode_value(kind: &FourCc, data: &[u8]) -> Option<String> {
+    let type_indicator = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) & 0x00FF_FFFF;
+    let value = &data[8..];
+    match type_indicator {
+        atom::TYPE_UTF8 => Some(String::from_utf8_lossy(value).into_owned()),
+        atom::TYPE_JPEG | atom::TYPE_PNG => None,
+        atom::TYPE_INTEGER => Some(read_int(value).to_string()),
+        atom::TYPE_IMPLICIT if (kind == b"trkn" || kind == b"disk") && value.len() >= 6 => {
+            let number = u16::from_be_bytes([value[2], value[3]]);
+            let total = u16::from_be_bytes([value[4], value[5]]);
+            Some(if total > 0 { format!("{}/{}", number, total) } else { number.to_string() })
+        }
+        atom::TYPE_IMPLICIT if value.len() <= 8 => Some(read_int(value).to_string()),
+        _ => None,
+    }
+}
+
+fn read_int(value: &[u8]) -> u64 {
+    value.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::JobOptions;
+    use crate::mapping::{FieldConfig, TableConfig};
+
+    #[test]
+    fn tags_for_drops_values_the_atoms_cannot_hold() {
+        let row = |tag: &str, value: &str| FieldConfig { tag: tag.to_string(), value: value.to_string(), join: None };
+        let config = TableConfig {
+            preset: None,
+            defaults: false,
+            fields: vec![
+                row("title", "{title}"),
+                row("season_number", "{title}"),
+                row("media_type", "Feature"),
+                row("hd_video", "1080P"),
+                row("not an atom", "x"),
+                row("----:com.nfo2tags", "x"),
+            ],
+        };
+        let backend = Mp4Backend::with_mapping(MappingTable::default().customize(&config).unwrap());
+        let nfo = Nfo::from_xml("<movie><title>Heat</title></movie>").unwrap();
+        let tags = backend.tags_for(&nfo);
+        let names: Vec<&str> = tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["title", "hd_video"]);
+        assert_eq!(tags.get("hd_video"), Some("1080p"));
+        assert_eq!(tag_items(&tags).len(), tags.len());
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nfirst";
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0second";
+
+    /// A folder with a small video and two covers, and a job for the video.
+    fn fixture(merge: Option<MergePolicy>) -> (tempfile::TempDir, Job) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = atom::make_box(*b"ftyp", b"isom\0\0\0\0");
+        bytes.extend(atom::make_box(atom::MOOV, &atom::full_box(atom::MVHD, 0, 0, &[0; 96])));
+        bytes.extend(atom::make_box(atom::MDAT, b"samples"));
+        let video = dir.path().join("movie.mp4");
+        fs::write(&video, bytes).unwrap();
+        fs::write(dir.path().join("first.png"), PNG).unwrap();
+        fs::write(dir.path().join("second.jpg"), JPEG).unwrap();
+        let job = Job::resolve(&video, None, None, &JobOptions { merge, ..JobOptions::default() });
+        (dir, job)
+    }
+
+    fn cover(dir: &tempfile::TempDir, name: &str, mime_type: &'static str) -> Cover {
+        Cover { path: dir.path().join(name), mime_type, landscape: false }
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> TagSet {
+        let mut tags = TagSet::new();
+        for (name, value) in pairs {
+            tags.push(name, value);
+        }
+        tags
+    }
+
+    /// Tags the fixture with a title, a stamp and the first cover, then
+    /// merges in `nfo` and the second cover.
+    fn merged(policy: MergePolicy, nfo: &TagSet) -> (TagSet, Vec<u8>) {
+        let backend = Mp4Backend::default();
+        let (dir, job) = fixture(Some(policy));
+        let existing = tags(&[("title", "Old"), ("encoder", "HandBrake"), ("----:com.nfo2tags:source", "stamp")]);
+        backend.apply(&job, &existing, Some(&cover(&dir, "first.png", "image/png"))).unwrap();
+        backend.merge(&job, nfo, Some(&cover(&dir, "second.jpg", "image/jpeg")), policy).unwrap();
+        let embedded = backend.read_cover(&job.output).unwrap().unwrap();
+        (backend.read_tags(&job.output).unwrap(), embedded.data)
+    }
+
+    #[test]
+    fn merge_policies_on_a_tagged_file() {
+        let nfo = tags(&[("title", "New"), ("genre", "Crime")]);
+
+        let (written, image) = merged(MergePolicy::NfoWins, &nfo);
+        assert_eq!(written.get_all("title"), ["New"]);
+        assert_eq!(written.get("genre"), Some("Crime"));
+        assert_eq!(written.get("encoder"), Some("HandBrake"));
+        assert!(written.get("----:com.nfo2tags:source").is_none());
+        assert_eq!(image, JPEG);
+
+        let (written, image) = merged(MergePolicy::ExistingWins, &nfo);
+        assert_eq!(written.get_all("title"), ["Old"]);
+        assert_eq!(written.get("genre"), Some("Crime"));
+        assert_eq!(image, JPEG);
+
+        let (written, image) = merged(MergePolicy::FillMissing, &nfo);
+        assert_eq!(written.get_all("title"), ["Old"]);
+        assert_eq!(written.get("genre"), Some("Crime"));
+        assert_eq!(image, PNG);
+    }
+
+    #[test]
+    fn fill_missing_adds_a_cover_to_a_file_without_one() {
+        let backend = Mp4Backend::default();
+        let (dir, job) = fixture(Some(MergePolicy::FillMissing));
+        backend.merge(&job, &tags(&[("title", "New")]), Some(&cover(&dir, "second.jpg", "image/jpeg")), MergePolicy::FillMissing).unwrap();
+        assert_eq!(backend.read_cover(&job.output).unwrap().unwrap().data, JPEG);
+        assert_eq!(backend.read_tags(&job.output).unwrap().get("title"), Some("New"));
+    }
+
+    #[test]
+    fn movie_round_trips_through_itunes_tags() {
+        let nfo = Nfo::from_xml(r#"<movie>
+            <title>Heat</title><sorttitle>Heat 1</sorttitle><mpaa>R</mpaa>
+            <plot>A group of professional bank robbers.</plot><outline>Cops and robbers.</outline>
+            <uniqueid type="imdb" default="true">tt0113277</uniqueid><uniqueid type="tmdb">949</uniqueid>
+            <genre>Crime</genre><genre>Drama</genre><tag>heist</tag><studio>Warner Bros.</studio>
+            <premiered>1995-12-15</premiered>
+            <actor><name>Robert De Niro</name><order>1</order></actor>
+            <actor><name>Al Pacino</name><order>0</order></actor>
+        </movie>"#).unwrap();
+        let backend = Mp4Backend::default();
+        let tags = backend.tags_for(&nfo);
+        assert_eq!(tags.get("media_type"), Some("Movie"));
+        let back = backend.nfo_from_tags(&tags);
+        assert_eq!(back.kind, NfoKind::Movie);
+        assert_eq!((back.title.as_str(), back.sorttitle.as_str()), ("Heat", "Heat 1"));
+        assert_eq!(back.plot, nfo.plot);
+        assert_eq!(back.genres, nfo.genres);
+        assert_eq!(back.tags, nfo.tags);
+        assert_eq!(back.studios, nfo.studios);
+        assert_eq!((back.premiered.as_str(), back.year), ("1995-12-15", Some(1995)));
+        let names: Vec<&str> = back.actors_in_order().iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Al Pacino", "Robert De Niro"]);
+        assert_eq!(back.imdb_id(), Some("tt0113277"));
+        assert_eq!(back.tmdb_id(), Some("949"));
+    }
+
+    #[test]
+    fn episode_round_trips_through_itunes_tags() {
+        let nfo = Nfo::from_xml("<episodedetails>
+            <title>Pilot</title><showtitle>Lost</showtitle><season>1</season><episode>3</episode>
+            <plot>The survivors.</plot><aired>2004-09-22</aired>
+        </episodedetails>").unwrap();
+        let backend = Mp4Backend::default();
+        let tags = backend.tags_for(&nfo);
+        assert_eq!(tags.get("media_type"), Some("TV Show"));
+        let back = backend.nfo_from_tags(&tags);
+        assert_eq!(back.kind, NfoKind::Episode);
+        assert_eq!((back.title.as_str(), back.showtitle.as_str()), ("Pilot", "Lost"));
+        assert_eq!((back.season, back.episode), (Some(1), Some(3)));
+        assert_eq!(back.plot, "The survivors.");
+        assert_eq!(back.release_date(), "2004-09-22");
+    }
+
+    #[test]
+    fn reads_back_the_legacy_tags() {
+        let nfo = Nfo::from_xml("<episodedetails>
+            <title>Pilot</title><showtitle>Lost</showtitle><season>1</season><episode>3</episode>
+            <plot>The survivors of a plane crash.</plot><outline>Survivors.</outline><genre>Drama</genre>
+        </episodedetails>").unwrap();
+        let backend = Mp4Backend::with_mapping(MappingTable::preset("mp4", Some("legacy")).unwrap());
+        let tags = backend.tags_for(&nfo);
+        assert_eq!(tags.get("episode_id"), Some("3"));
+        let back = backend.nfo_from_tags(&tags);
+        assert_eq!(back.kind, NfoKind::Episode);
+        assert_eq!((back.season, back.episode), (Some(1), Some(3)));
+        assert_eq!((back.plot.as_str(), back.outline.as_str()), ("The survivors of a plane crash.", "Survivors."));
+        assert_eq!(back.genres, ["Drama"]);
+    }
+}
+
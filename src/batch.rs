@@ -0,0 +1,236 @@
+use crate::backend::Registry;
+use crate::job::{FileResult, Job};
+use crate::journal::{self, Journal, State};
+use crate::process::process_file_with;
+use log::{info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// How many jobs may run at once, overall and per backend.
+#[derive(Debug, Clone)]
+pub struct BatchLimits {
+    pub workers: usize,
+    /// Lower caps for backends whose edits are heavy, keyed by backend name.
+    pub per_backend: HashMap<String, usize>,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits { workers: 1, per_backend: HashMap::new() }
+    }
+}
+
+impl BatchLimits {
+    fn limit(&self, backend: &str) -> usize {
+        self.per_backend.get(backend).copied().unwrap_or(self.workers).clamp(1, self.workers.max(1))
+    }
+}
+
+/// Totals for a finished batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub processed: usize,
+    /// Files left alone because their NFO and cover haven't changed.
+    pub skipped: usize,
+    pub errors: usize,
+}
+
+struct Queue {
+    pending: VecDeque<(usize, &'static str)>,
+    running: HashMap<&'static str, usize>,
+}
+
+/// Runs `process_file_with` over `jobs` on a pool of worker threads.
+/// `on_done` is called on the calling thread, one job at a time, as results
+/// come in, so it can print or log without interleaving. With a `journal`,
+/// every job's progress is recorded in it.
+pub fn process_batch(
+    jobs: &[Job],
+    registry: &Registry,
+    limits: &BatchLimits,
+    journal: Option<&Journal>,
+    mut on_done: impl FnMut(&Job, &io::Result<FileResult>),
+) -> BatchSummary {
+    let mut summary = BatchSummary::default();
+    let mut record = |job: &Job, result: io::Result<FileResult>| {
+        match &result {
+            Ok(done) if done.skipped => summary.skipped += 1,
+            Ok(_) => summary.processed += 1,
+            Err(_) => summary.errors += 1,
+        }
+        if let Some(journal) = journal {
+            let state = match &result {
+                Ok(done) => match journal::fingerprint_file(&done.output) {
+                    Ok(fingerprint) => State::Done { output: done.output.clone(), fingerprint },
+                    Err(e) => State::Failed { error: format!("Could not fingerprint the result: {}", e) },
+                },
+                Err(e) => State::Failed { error: e.to_string() },
+            };
+            if let Err(e) = journal.record(&job.video, state) {
+                warn!("  Could not update the journal {}: {}", journal.path().display(), e);
+            }
+        }
+        on_done(job, &result);
+    };
+
+    let mut pending = VecDeque::new();
+    for (index, job) in jobs.iter().enumerate() {
+        match registry.detect(&job.video) {
+            Ok(backend) => {
+                if let Some(journal) = journal {
+                    if let Err(e) = journal.record(&job.video, State::Pending) {
+                        warn!("  Could not update the journal {}: {}", journal.path().display(), e);
+                    }
+                }
+                pending.push_back((index, backend.name()))
+            }
+            Err(e) => record(job, Err(e)),
+        }
+    }
+    let queued = pending.len();
+    let queue = Mutex::new(Queue { pending, running: HashMap::new() });
+    let wake = Condvar::new();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..limits.workers.max(1).min(queued) {
+            let sender = sender.clone();
+            let (queue, wake) = (&queue, &wake);
+            scope.spawn(move || loop {
+                let (index, backend) = {
+                    let mut state = queue.lock().unwrap();
+                    loop {
+                        if state.pending.is_empty() {
+                            return;
+                        }
+                        let free = state.pending.iter().position(|(_, backend)| {
+                            state.running.get(backend).copied().unwrap_or(0) < limits.limit(backend)
+                        });
+                        if let Some(position) = free {
+                            let next = state.pending.remove(position).expect("position is in range");
+                            *state.running.entry(next.1).or_insert(0) += 1;
+                            break next;
+                        }
+                        state = wake.wait(state).unwrap();
+                    }
+                };
+                let job = &jobs[index];
+                if let Some(journal) = journal {
+                    let target = registry.get(backend).map(|b| b.target(job)).filter(|t| *t != job.video);
+                    let marker = match target {
+                        Some(_) => None,
+                        None => journal::in_place_marker(&job.video).ok(),
+                    };
+                    if let Err(e) = journal.record(&job.video, State::InProgress { temp: target, marker }) {
+                        warn!("  Could not update the journal {}: {}", journal.path().display(), e);
+                    }
+                }
+                let result = process_file_with(job, registry);
+                *queue.lock().unwrap().running.get_mut(backend).expect("counted when taken") -= 1;
+                wake.notify_all();
+                if sender.send((index, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+        for (index, result) in receiver {
+            record(&jobs[index], result);
+        }
+    });
+
+    summary
+}
+
+/// Drops jobs the journal says are finished and cleans up after ones that were
+/// interrupted. Failed and never-started jobs are kept so they run again; ones
+/// that need checking by hand are kept only for `--force`.
+/// Returns the jobs left to run and how many were skipped.
+pub fn resume(jobs: Vec<Job>, journal: &Journal, registry: &Registry) -> io::Result<(Vec<Job>, usize)> {
+    let mut remaining = Vec::new();
+    let mut skipped = 0;
+    for job in jobs {
+        match journal.state(&job.video) {
+            Some(State::Done { .. }) if journal.is_done(&job.video) => {
+                info!("  Already done, skipping: {}", job.video.display());
+                skipped += 1;
+                continue;
+            }
+            Some(State::InProgress { temp, marker }) => {
+                let readable = |video: &std::path::Path| {
+                    registry.detect(video).and_then(|b| b.summarize(video)).is_ok()
+                };
+                if !journal::roll_back(temp.as_deref(), marker.as_deref(), &job.video, readable)? {
+                    warn!("  Interrupted run left {} in a state that needs checking by hand; skipping", job.video.display());
+                    journal.record(&job.video, State::NeedsCheck {
+                        reason: "Interrupted mid-write; check the file by hand".to_string(),
+                    })?;
+                    skipped += 1;
+                    continue;
+                }
+                info!("  Retrying interrupted job: {}", job.video.display());
+            }
+            Some(State::NeedsCheck { reason }) if !job.force => {
+                warn!("  {}, skipping until --force: {}", reason, job.video.display());
+                skipped += 1;
+                continue;
+            }
+            Some(State::NeedsCheck { .. }) => info!("  Retrying checked job: {}", job.video.display()),
+            Some(State::Failed { error }) => info!("  Retrying failed job ({}): {}", error, job.video.display()),
+            _ => {}
+        }
+        remaining.push(job);
+    }
+    Ok((remaining, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::JobOptions;
+    use std::fs;
+
+    #[test]
+    fn backend_limits_stay_between_one_and_the_worker_count() {
+        let per_backend = [("mkv", 2), ("mp4", 8), ("avi", 0)].into_iter().map(|(n, l)| (n.to_string(), l)).collect();
+        let limits = BatchLimits { workers: 4, per_backend };
+        assert_eq!(limits.limit("mkv"), 2);
+        assert_eq!(limits.limit("mp4"), 4, "capped at the worker count");
+        assert_eq!(limits.limit("avi"), 1, "never below one");
+        assert_eq!(limits.limit("webm"), 4, "uncapped backends use every worker");
+
+        let limits = BatchLimits { workers: 0, per_backend: HashMap::new() };
+        assert_eq!(limits.limit("mkv"), 1);
+        assert_eq!(BatchLimits::default().limit("mkv"), 1);
+    }
+
+    #[test]
+    fn files_needing_a_check_wait_for_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = dir.path().join("video.mkv");
+        fs::write(&video, b"not really a video").unwrap();
+        let journal_path = dir.path().join("journal.jsonl");
+        let journal = Journal::create(&journal_path).unwrap();
+        journal.record(&video, State::InProgress { temp: None, marker: Some("stale".to_string()) }).unwrap();
+        drop(journal);
+
+        let registry = Registry::default();
+        let jobs = || vec![Job::resolve(&video, None, None, &JobOptions::default())];
+        let journal = Journal::open(&journal_path).unwrap();
+        let (remaining, skipped) = resume(jobs(), &journal, &registry).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(skipped, 1);
+
+        let journal = Journal::open(&journal_path).unwrap();
+        assert!(matches!(journal.state(&video), Some(State::NeedsCheck { .. })));
+        let (remaining, skipped) = resume(jobs(), &journal, &registry).unwrap();
+        assert_eq!((remaining.len(), skipped), (0, 1), "still skipped on the next resume");
+
+        let forced = JobOptions { force: true, ..JobOptions::default() };
+        let (remaining, _) = resume(vec![Job::resolve(&video, None, None, &forced)], &journal, &registry).unwrap();
+        assert_eq!(remaining.len(), 1);
+    }
+}
@@ -0,0 +1,203 @@
+//! Settings loaded from a TOML file, grouped into named profiles so each
+//! library can keep its own cover suffix, output folder and merge mode.
+//!
+//! ```toml
+//! default_profile = "movies"
+//!
+//! [profiles.movies]
+//! cover_name = "-poster"
+//! output = "/fast-scratch"
+//! delete = true
+//!
+//! [profiles.tv]
+//! cover_name = "-thumb"
+//! merge = "nfo-wins"
+//! ```
+//!
+//! `[mapping.<backend>]` tables, at the top level or inside a profile, change
+//! which NFO fields become which tags; see [`crate::mapping`].
+
+use crate::backend::{MkvBackend, Mp4Backend, Registry};
+use crate::mapping::{MappingTable, TableConfig};
+use crate::nfo::Inherit;
+use crate::tags::MergePolicy;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Options a profile can set. Anything left out falls back to the command
+/// line defaults, and flags given on the command line win over the profile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub cover_name: Option<String>,
+    pub output: Option<PathBuf>,
+    pub delete: Option<bool>,
+    pub merge: Option<MergePolicy>,
+    pub inherit: Option<Inherit>,
+    /// Where to look for NFOs and covers, replacing the built-in lists.
+    pub nfo_patterns: Option<Vec<String>>,
+    pub cover_patterns: Option<Vec<String>>,
+    pub jobs: Option<usize>,
+    pub mp4_jobs: Option<usize>,
+    /// Mapping changes applied after the top-level ones.
+    pub mapping: BTreeMap<String, TableConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Profile used when none is named on the command line.
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Mapping changes keyed by backend name, "mkv" or "mp4".
+    pub mapping: BTreeMap<String, TableConfig>,
+}
+
+impl Config {
+    /// `nfo2tags/config.toml` in the user's config directory, e.g.
+    /// `~/.config/nfo2tags/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("nfo2tags").join("config.toml"))
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Config> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid config file {}: {}", path.display(), e.message())
+        ))
+    }
+
+    /// Loads `path`, or the file at [`Config::default_path`] if there is one.
+    /// With neither, the config is empty.
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        match path {
+            Some(path) => Config::from_file(path),
+            None => match Config::default_path().filter(|p| p.is_file()) {
+                Some(path) => Config::from_file(&path),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+
+    /// The profile called `name`, or the default profile if `name` is None.
+    /// Without either, an empty profile that changes nothing.
+    pub fn profile(&self, name: Option<&str>) -> io::Result<Profile> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(Profile::default());
+        };
+        self.profiles.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No profile named {} in the config. Profiles: {}", name,
+                    if known.is_empty() { "none".to_string() } else { known.join(", ") })
+            )
+        })
+    }
+
+    /// The built-in backends with their mapping tables changed by this config
+    /// and then by `profile`.
+    pub fn registry(&self, profile: &Profile) -> io::Result<Registry> {
+        for name in self.mapping.keys().chain(profile.mapping.keys()) {
+            if name != "mkv" && name != "mp4" {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("No backend named {} to map tags for, expected mkv or mp4", name)));
+            }
+        }
+        let table = |name: &str| -> io::Result<MappingTable> {
+            let preset = profile.mapping.get(name).and_then(|c| c.preset.as_deref())
+                .or_else(|| self.mapping.get(name).and_then(|c| c.preset.as_deref()));
+            let table = MappingTable::preset(name, preset)?;
+            let table = match self.mapping.get(name) {
+                Some(config) => table.customize(config)?,
+                None => table,
+            };
+            match profile.mapping.get(name) {
+                Some(config) => table.customize(config),
+                None => Ok(table),
+            }
+        };
+        let mut registry = Registry::empty();
+        registry.register(Box::new(Mp4Backend::with_mapping(table("mp4")?)));
+        registry.register(Box::new(MkvBackend::with_mapping(table("mkv")?)));
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfo::Nfo;
+
+    const CONFIG: &str = r#"
+        default_profile = "movies"
+
+        [mapping.mkv]
+        fields = [{ tag = "COMMENT", value = "from the top level" }]
+
+        [profiles.movies]
+        cover_name = "-poster"
+        merge = "fill-missing"
+
+        [profiles.tv]
+        cover_name = "-thumb"
+        inherit = "show-wins"
+
+        [profiles.tv.mapping.mkv]
+        preset = "legacy"
+        fields = [{ tag = "COMMENT", value = "from the profile" }]
+    "#;
+
+    fn config() -> Config {
+        toml::from_str(CONFIG).unwrap()
+    }
+
+    fn mkv_tags(config: &Config, profile: &Profile) -> crate::tags::TagSet {
+        let registry = config.registry(profile).unwrap();
+        let nfo = Nfo::from_xml("<movie><title>Heat</title><director>Michael Mann</director></movie>").unwrap();
+        registry.get("mkv").unwrap().tags_for(&nfo)
+    }
+
+    #[test]
+    fn profile_by_name_or_default() {
+        let config = config();
+        assert_eq!(config.profile(None).unwrap().merge, Some(MergePolicy::FillMissing));
+        let tv = config.profile(Some("tv")).unwrap();
+        assert_eq!(tv.cover_name.as_deref(), Some("-thumb"));
+        assert_eq!(tv.inherit, Some(Inherit::ShowWins));
+        assert_eq!(tv.merge, None);
+
+        let error = config.profile(Some("music")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(error.to_string().contains("movies, tv"));
+
+        assert_eq!(Config::default().profile(None).unwrap(), Profile::default());
+    }
+
+    #[test]
+    fn registry_applies_top_level_then_profile_mapping() {
+        let config = config();
+        let tags = mkv_tags(&config, &config.profile(None).unwrap());
+        assert_eq!(tags.get("COMMENT"), Some("from the top level"));
+        assert_eq!(tags.get("DIRECTOR"), Some("Michael Mann"));
+
+        let tags = mkv_tags(&config, &config.profile(Some("tv")).unwrap());
+        assert_eq!(tags.get_all("COMMENT"), ["from the profile"]);
+        assert_eq!(tags.get("Director"), Some("Michael Mann"), "the profile's preset wins");
+
+        let tags = mkv_tags(&Config::default(), &Profile::default());
+        assert_eq!(tags.get("COMMENT"), None);
+    }
+
+    #[test]
+    fn registry_rejects_unknown_backends_and_presets() {
+        let bad: Config = toml::from_str("[mapping.avi]\nfields = []").unwrap();
+        assert_eq!(bad.registry(&Profile::default()).err().unwrap().kind(), ErrorKind::InvalidInput);
+        let bad: Config = toml::from_str("[mapping.mp4]\npreset = \"nope\"").unwrap();
+        assert!(bad.registry(&Profile::default()).is_err());
+        assert!(toml::from_str::<Config>("[profiles.x]\ncover = \"-poster\"").is_err(), "unknown keys are errors");
+    }
+}
@@ -0,0 +1,50 @@
+use image::{open, GenericImageView};
+use serde::Serialize;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// A cover image ready to be embedded.
+#[derive(Debug, Clone, Serialize)]
+pub struct Cover {
+    pub path: PathBuf,
+    pub mime_type: &'static str,
+    pub landscape: bool,
+}
+
+impl Cover {
+    pub fn load(path: &Path) -> io::Result<Cover> {
+        let mime_type = if path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            "image/png"
+        } else {
+            "image/jpeg"
+        };
+        let image = open(path).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("Failed to open image: {}", e))
+        })?;
+        let (width, height) = image.dimensions();
+        Ok(Cover {
+            path: path.to_path_buf(),
+            mime_type,
+            landscape: width > height,
+        })
+    }
+}
+
+/// Cover art found inside a video file.
+#[derive(Debug, Clone)]
+pub struct EmbeddedCover {
+    pub data: Vec<u8>,
+    pub mime_type: &'static str,
+}
+
+impl EmbeddedCover {
+    /// Wraps image bytes, telling PNG from JPEG by the file signature.
+    pub fn from_bytes(data: Vec<u8>) -> EmbeddedCover {
+        let mime_type = if data.starts_with(b"\x89PNG\r\n\x1a\n") { "image/png" } else { "image/jpeg" };
+        EmbeddedCover { data, mime_type }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        if self.mime_type == "image/png" { "png" } else { "jpg" }
+    }
+}
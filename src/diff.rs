@@ -0,0 +1,201 @@
+use crate::backend::Registry;
+use crate::job::{Job, Only};
+use crate::process::prepare;
+use crate::tags::TagSet;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// Only the NFO has it.
+    Added,
+    /// Only the file has it; it would be lost.
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+/// One tag name, with every value it has in the file and from the NFO.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDiff {
+    pub name: String,
+    pub current: Vec<String>,
+    pub proposed: Vec<String>,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CoverDiff {
+    /// Whether the file already carries cover art.
+    pub embedded: bool,
+    /// The image that would replace it.
+    pub proposed: Option<PathBuf>,
+}
+
+/// How the tags in a file compare to what `process_file` would write.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileDiff {
+    pub video: PathBuf,
+    pub backend: &'static str,
+    pub fields: Vec<FieldDiff>,
+    /// None when the run leaves the cover alone.
+    pub cover: Option<CoverDiff>,
+}
+
+impl FileDiff {
+    /// Fields in the file that the NFO has nothing for.
+    pub fn lost_fields(&self) -> Vec<&FieldDiff> {
+        self.fields.iter().filter(|f| f.change == Change::Removed).collect()
+    }
+
+    /// True if writing would drop tags or cover art with no replacement.
+    pub fn loses_information(&self) -> bool {
+        !self.lost_fields().is_empty() || self.loses_cover()
+    }
+
+    fn loses_cover(&self) -> bool {
+        self.cover.as_ref().is_some_and(|c| c.embedded && c.proposed.is_none())
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.fields.iter().any(|f| f.change != Change::Unchanged)
+            || self.cover.as_ref().is_some_and(|c| c.proposed.is_some() || c.embedded)
+    }
+}
+
+impl fmt::Display for FileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.video.display(), self.backend)?;
+        for field in &self.fields {
+            let current = quoted(&field.current);
+            let proposed = quoted(&field.proposed);
+            match field.change {
+                Change::Added => writeln!(f, "  + {}: {}", field.name, proposed)?,
+                Change::Removed => writeln!(f, "  - {}: {}", field.name, current)?,
+                Change::Changed => writeln!(f, "  ~ {}: {} -> {}", field.name, current, proposed)?,
+                Change::Unchanged => {}
+            }
+        }
+        if let Some(cover) = &self.cover {
+            match (&cover.proposed, cover.embedded) {
+                (Some(path), true) => writeln!(f, "  ~ cover: embedded image replaced by {}", path.display())?,
+                (Some(path), false) => writeln!(f, "  + cover: {}", path.display())?,
+                (None, true) => writeln!(f, "  - cover: embedded image removed")?,
+                (None, false) => {}
+            }
+        }
+        if self.loses_information() {
+            let mut lost: Vec<&str> = self.lost_fields().iter().map(|f| f.name.as_str()).collect();
+            if self.loses_cover() {
+                lost.push("cover");
+            }
+            writeln!(f, "  ! Loses information not in the NFO: {}", lost.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn quoted(values: &[String]) -> String {
+    let values: Vec<String> = values.iter().map(|v| format!("{:?}", v)).collect();
+    values.join(", ")
+}
+
+/// Compares the tags in `job.video` with the ones its NFO would produce.
+pub fn diff_file(job: &Job) -> io::Result<FileDiff> {
+    diff_file_with(job, &Registry::default())
+}
+
+pub fn diff_file_with(job: &Job, registry: &Registry) -> io::Result<FileDiff> {
+    let (backend, plan) = prepare(job, registry)?;
+    // The provenance stamp changes with every new source, so it is no field
+    // the user would want listed.
+    let stamp = backend.provenance_tag();
+    let fields = match &plan.tags {
+        Some(tags) => compare(&backend.read_tags(&job.video)?.without(stamp), &tags.without(stamp)),
+        None => Vec::new(),
+    };
+    let cover = match job.only {
+        Some(Only::Tags) => None,
+        _ => Some(CoverDiff {
+            embedded: backend.read_cover(&job.video)?.is_some(),
+            proposed: plan.cover.map(|c| c.path),
+        }),
+    };
+    Ok(FileDiff {
+        video: job.video.clone(),
+        backend: plan.backend,
+        fields,
+        cover,
+    })
+}
+
+/// Pairs up tags by name, ignoring case. NFO names come first, in order.
+pub fn compare(current: &TagSet, proposed: &TagSet) -> Vec<FieldDiff> {
+    let mut names: Vec<&str> = Vec::new();
+    for tag in proposed.iter().chain(current.iter()) {
+        if !names.iter().any(|n| n.eq_ignore_ascii_case(&tag.name)) {
+            names.push(&tag.name);
+        }
+    }
+    names.into_iter().map(|name| {
+        let current: Vec<String> = current.get_all(name).into_iter().map(String::from).collect();
+        let proposed: Vec<String> = proposed.get_all(name).into_iter().map(String::from).collect();
+        let change = if current.is_empty() {
+            Change::Added
+        } else if proposed.is_empty() {
+            Change::Removed
+        } else if current == proposed {
+            Change::Unchanged
+        } else {
+            Change::Changed
+        };
+        FieldDiff { name: name.to_string(), current, proposed, change }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> TagSet {
+        let mut tags = TagSet::new();
+        for (name, value) in pairs {
+            tags.push(name, value);
+        }
+        tags
+    }
+
+    fn changes(diff: &[FieldDiff]) -> Vec<(&str, Change)> {
+        diff.iter().map(|f| (f.name.as_str(), f.change)).collect()
+    }
+
+    #[test]
+    fn compare_classifies_each_name() {
+        let current = tags(&[("TITLE", "Heat"), ("GENRE", "Crime"), ("COMMENT", "ripped"), ("DIRECTOR", "Mann")]);
+        let proposed = tags(&[("DATE_RELEASED", "1995"), ("title", "Heat"), ("GENRE", "Crime"), ("GENRE", "Drama"), ("DIRECTOR", "Michael Mann")]);
+        let diff = compare(&current, &proposed);
+        assert_eq!(changes(&diff), [
+            ("DATE_RELEASED", Change::Added),
+            ("title", Change::Unchanged),
+            ("GENRE", Change::Changed),
+            ("DIRECTOR", Change::Changed),
+            ("COMMENT", Change::Removed),
+        ]);
+        assert_eq!(diff[2].current, ["Crime"]);
+        assert_eq!(diff[2].proposed, ["Crime", "Drama"]);
+    }
+
+    #[test]
+    fn compare_counts_value_order() {
+        let diff = compare(&tags(&[("ACTOR", "A"), ("ACTOR", "B")]), &tags(&[("ACTOR", "B"), ("ACTOR", "A")]));
+        assert_eq!(changes(&diff), [("ACTOR", Change::Changed)]);
+    }
+
+    #[test]
+    fn compare_of_empty_sets_is_empty() {
+        assert!(compare(&TagSet::new(), &TagSet::new()).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! Just enough EBML to read and rewrite Matroska metadata elements.
+
+use std::io::{self, Error, ErrorKind, Read};
+
+pub const EBML_HEADER: u32 = 0x1A45DFA3;
+pub const DOC_TYPE: u32 = 0x4282;
+pub const SEGMENT: u32 = 0x18538067;
+pub const SEEK_HEAD: u32 = 0x114D9B74;
+pub const SEEK: u32 = 0x4DBB;
+pub const SEEK_ID: u32 = 0x53AB;
+pub const SEEK_POSITION: u32 = 0x53AC;
+pub const INFO: u32 = 0x1549A966;
+pub const TIMECODE_SCALE: u32 = 0x2AD7B1;
+pub const DURATION: u32 = 0x4489;
+pub const TITLE: u32 = 0x7BA9;
+pub const TRACKS: u32 = 0x1654AE6B;
+pub const TRACK_ENTRY: u32 = 0xAE;
+pub const TRACK_TYPE: u32 = 0x83;
+pub const CODEC_ID: u32 = 0x86;
+pub const CLUSTER: u32 = 0x1F43B675;
+pub const CUES: u32 = 0x1C53BB6B;
+pub const CHAPTERS: u32 = 0x1043A770;
+pub const ATTACHMENTS: u32 = 0x1941A469;
+pub const ATTACHED_FILE: u32 = 0x61A7;
+pub const FILE_DESCRIPTION: u32 = 0x467E;
+pub const FILE_NAME: u32 = 0x466E;
+pub const FILE_MIME_TYPE: u32 = 0x4660;
+pub const FILE_DATA: u32 = 0x465C;
+pub const FILE_UID: u32 = 0x46AE;
+pub const TAGS: u32 = 0x1254C367;
+pub const TAG: u32 = 0x7373;
+pub const TARGETS: u32 = 0x63C0;
+pub const TARGET_TYPE_VALUE: u32 = 0x68CA;
+pub const TARGET_TYPE: u32 = 0x63CA;
+pub const TAG_TRACK_UID: u32 = 0x63C5;
+pub const TAG_EDITION_UID: u32 = 0x63C9;
+pub const TAG_CHAPTER_UID: u32 = 0x63C4;
+pub const TAG_ATTACHMENT_UID: u32 = 0x63C6;
+pub const SIMPLE_TAG: u32 = 0x67C8;
+pub const TAG_NAME: u32 = 0x45A3;
+pub const TAG_LANGUAGE: u32 = 0x447A;
+pub const TAG_DEFAULT: u32 = 0x4484;
+pub const TAG_STRING: u32 = 0x4487;
+pub const TAG_BINARY: u32 = 0x4485;
+pub const VOID: u32 = 0xEC;
+pub const CRC32: u32 = 0xBF;
+
+/// An element header. `size` is `None` for the "unknown size" marker.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub id: u32,
+    pub size: Option<u64>,
+    pub header_len: usize,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn vint_len(first: u8, max: usize) -> io::Result<usize> {
+    let len = first.leading_zeros() as usize + 1;
+    if len > max {
+        return Err(invalid("Invalid EBML variable-length integer"));
+    }
+    Ok(len)
+}
+
+/// Reads an element header from a stream.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<Header> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let id_len = vint_len(first[0], 4)?;
+    let mut id = first[0] as u32;
+    for _ in 1..id_len {
+        reader.read_exact(&mut first)?;
+        id = (id << 8) | first[0] as u32;
+    }
+
+    reader.read_exact(&mut first)?;
+    let size_len = vint_len(first[0], 8)?;
+    let mut size = (first[0] as u64) & (0xFF >> size_len);
+    let mut all_ones = size == (0xFF >> size_len) as u64;
+    for _ in 1..size_len {
+        reader.read_exact(&mut first)?;
+        size = (size << 8) | first[0] as u64;
+        all_ones &= first[0] == 0xFF;
+    }
+    Ok(Header {
+        id,
+        size: if all_ones { None } else { Some(size) },
+        header_len: id_len + size_len,
+    })
+}
+
+/// A child element inside an in-memory payload. `raw` includes the header.
+#[derive(Debug, Clone, Copy)]
+pub struct Child<'a> {
+    pub id: u32,
+    pub data: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+/// Splits a master element's payload into its children.
+pub fn children(payload: &[u8]) -> io::Result<Vec<Child<'_>>> {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos < payload.len() {
+        let mut cursor = &payload[pos..];
+        let header = read_header(&mut cursor)?;
+        let size = header.size.ok_or_else(|| invalid("Unknown-size element inside a master element"))? as usize;
+        let start = pos + header.header_len;
+        let end = start.checked_add(size).filter(|end| *end <= payload.len())
+            .ok_or_else(|| invalid("EBML element runs past its parent"))?;
+        out.push(Child { id: header.id, data: &payload[start..end], raw: &payload[pos..end] });
+        pos = end;
+    }
+    Ok(out)
+}
+
+pub fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+}
+
+pub fn read_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+pub fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+pub fn encode_id(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(3);
+    bytes[skip..].to_vec()
+}
+
+/// Encodes `size` in exactly `width` bytes, if it fits.
+pub fn encode_size_width(size: u64, width: usize) -> Option<Vec<u8>> {
+    if !(1..=8).contains(&width) || size >= (1u64 << (7 * width)) - 1 {
+        return None;
+    }
+    let marked = size | (1u64 << (7 * width));
+    Some(marked.to_be_bytes()[8 - width..].to_vec())
+}
+
+pub fn encode_size(size: u64) -> Vec<u8> {
+    (1..=8).find_map(|width| encode_size_width(size, width)).expect("EBML size out of range")
+}
+
+pub fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = encode_id(id);
+    out.extend(encode_size(payload.len() as u64));
+    out.extend_from_slice(payload);
+    out
+}
+
+pub fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+    element(id, &bytes[skip..])
+}
+
+pub fn string_element(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+/// Header for a Void element spanning `total_len` bytes. The payload is left
+/// as whatever already sits on disk; readers skip it.
+pub fn void_header(total_len: u64) -> Option<Vec<u8>> {
+    if total_len < 2 {
+        return None;
+    }
+    let width = if total_len - 2 <= 126 { 1 } else { 8 };
+    let mut out = encode_id(VOID);
+    out.extend(encode_size_width(total_len - 1 - width as u64, width)?);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_fits_width() {
+        assert_eq!(encode_size_width(0, 1), Some(vec![0x80]));
+        assert_eq!(encode_size_width(126, 1), Some(vec![0xFE]));
+        // All ones is the unknown-size marker, so 127 needs two bytes.
+        assert_eq!(encode_size_width(127, 1), None);
+        assert_eq!(encode_size_width(127, 2), Some(vec![0x40, 0x7F]));
+        assert_eq!(encode_size_width(5, 8), Some(vec![0x01, 0, 0, 0, 0, 0, 0, 5]));
+        assert_eq!(encode_size_width(1 << 56, 8), None);
+        assert_eq!(encode_size_width(1, 0), None);
+        assert_eq!(encode_size_width(1, 9), None);
+        assert_eq!(encode_size(200), vec![0x40, 0xC8]);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for size in [0, 1, 126, 127, 16_382, 16_383, 1 << 20, (1 << 56) - 2] {
+            let mut bytes = encode_id(TAGS);
+            bytes.extend(encode_size(size));
+            let header = read_header(&mut bytes.as_slice()).unwrap();
+            assert_eq!((header.id, header.size, header.header_len), (TAGS, Some(size), bytes.len()));
+        }
+        let header = read_header(&mut [0xEC, 0xFF].as_slice()).unwrap();
+        assert_eq!((header.id, header.size), (VOID, None));
+    }
+
+    #[test]
+    fn void_header_spans_its_length() {
+        assert_eq!(void_header(0), None);
+        assert_eq!(void_header(1), None);
+        assert_eq!(void_header(2), Some(vec![0xEC, 0x80]));
+        assert_eq!(void_header(128), Some(vec![0xEC, 0xFE]));
+        assert_eq!(void_header(129), Some(vec![0xEC, 0x01, 0, 0, 0, 0, 0, 0, 120]));
+        for total in 2..300 {
+            let header = read_header(&mut void_header(total).unwrap().as_slice()).unwrap();
+            assert_eq!(header.id, VOID);
+            assert_eq!(header.header_len as u64 + header.size.unwrap(), total, "Void of {} bytes", total);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::backend::Registry;
+use log::{info, warn};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Settings for turning embedded tags back into sidecar files.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Appended to the video's stem to name the poster, as in `Movie-poster.jpg`.
+    pub cover_suffix: String,
+    /// Replace NFO and poster files that already exist.
+    pub overwrite: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            cover_suffix: "-poster".to_string(),
+            overwrite: false,
+        }
+    }
+}
+
+/// The sidecar files `extract_file` wrote.
+#[derive(Debug, Clone)]
+pub struct ExtractResult {
+    pub video: PathBuf,
+    pub nfo: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+}
+
+/// Writes an NFO and poster next to `video` from its embedded tags and cover.
+pub fn extract_file(video: &Path, options: &ExtractOptions) -> io::Result<ExtractResult> {
+    extract_file_with(video, options, &Registry::default())
+}
+
+pub fn extract_file_with(video: &Path, options: &ExtractOptions, registry: &Registry) -> io::Result<ExtractResult> {
+    let backend = registry.detect(video)?;
+    let tags = backend.read_tags(video)?;
+    let cover = backend.read_cover(video)?;
+    if tags.is_empty() && cover.is_none() {
+        return Err(Error::new(ErrorKind::NotFound, "No tags or cover art embedded in the file"));
+    }
+
+    let mut result = ExtractResult { video: video.to_path_buf(), nfo: None, cover: None };
+
+    if !tags.is_empty() {
+        let nfo_path = video.with_extension("nfo");
+        if nfo_path.exists() && !options.overwrite {
+            warn!("  NFO already exists, leaving it alone: {}", nfo_path.display());
+        } else {
+            backend.nfo_from_tags(&tags).write_file(&nfo_path)?;
+            info!("  Wrote NFO file: {}", nfo_path.display());
+            result.nfo = Some(nfo_path);
+        }
+    }
+
+    if let Some(cover) = cover {
+        let mut name = OsString::from(video.file_stem().unwrap_or_default());
+        name.push(&options.cover_suffix);
+        name.push(".");
+        name.push(cover.extension());
+        let cover_path = video.with_file_name(name);
+        if cover_path.exists() && !options.overwrite {
+            warn!("  Cover already exists, leaving it alone: {}", cover_path.display());
+        } else {
+            fs::write(&cover_path, &cover.data)?;
+            info!("  Wrote cover file: {}", cover_path.display());
+            result.cover = Some(cover_path);
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,400 @@
+//! In-place editing of the iTunes-style metadata list (`moov/udta/meta/ilst`)
+//! in MP4 files. Only the `moov` box is rewritten: over its old location when
+//! it fits next to `free` padding, and otherwise appended to the end with its
+//! old location turned into `free` space. The media data never moves, so
+//! chunk offsets stay valid.
+
+use crate::atom::{self, FourCc};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+struct TopBox {
+    kind: FourCc,
+    offset: u64,
+    len: u64,
+}
+
+pub struct Mp4File {
+    file: File,
+    len: u64,
+    boxes: Vec<TopBox>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn is_padding(kind: &FourCc) -> bool {
+    *kind == atom::FREE || *kind == atom::SKIP
+}
+
+impl Mp4File {
+    /// Opens a file for reading, so read-only files and mounts work too.
+    pub fn open(path: &Path) -> io::Result<Mp4File> {
+        Mp4File::load(File::open(path)?, path)
+    }
+
+    /// Opens a file to be edited in place.
+    pub fn edit(path: &Path) -> io::Result<Mp4File> {
+        Mp4File::load(OpenOptions::new().read(true).write(true).open(path)?, path)
+    }
+
+    fn load(file: File, path: &Path) -> io::Result<Mp4File> {
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file.try_clone()?);
+        let mut boxes = Vec::new();
+        let mut pos = 0u64;
+        while pos + 8 <= len {
+            reader.seek(SeekFrom::Start(pos))?;
+            let header = atom::read_header(&mut reader, len - pos)?;
+            if pos + header.size > len {
+                return Err(invalid(format!("{} is truncated", path.display())));
+            }
+            boxes.push(TopBox { kind: header.kind, offset: pos, len: header.size });
+            pos += header.size;
+        }
+        if !boxes.iter().any(|b| b.kind == atom::MOOV) {
+            return Err(invalid(format!("{} has no moov box", path.display())));
+        }
+        Ok(Mp4File { file, len, boxes })
+    }
+
+    fn moov_index(&self) -> usize {
+        self.boxes.iter().position(|b| b.kind == atom::MOOV).expect("checked in open")
+    }
+
+    /// Payload of the `moov` box.
+    pub fn read_moov(&mut self) -> io::Result<Vec<u8>> {
+        let moov = self.boxes[self.moov_index()];
+        self.file.seek(SeekFrom::Start(moov.offset))?;
+        let header = atom::read_header(&mut self.file, moov.len)?;
+        let mut data = vec![0u8; (moov.len - header.header_len as u64) as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Payload of `moov/udta/meta/ilst`, if the file has one.
+    pub fn read_ilst(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let moov = self.read_moov()?;
+        let Some(udta) = atom::children(&moov)?.into_iter().find(|c| c.kind == atom::UDTA) else {
+            return Ok(None);
+        };
+        let Some(meta) = atom::children(udta.data)?.into_iter().find(|c| c.kind == atom::META) else {
+            return Ok(None);
+        };
+        let ilst = atom::children(meta_children(meta.data))?.into_iter().find(|c| c.kind == atom::ILST);
+        Ok(ilst.map(|c| c.data.to_vec()))
+    }
+
+    /// Replaces the `ilst` payload, or removes the list when `ilst` is `None`.
+    pub fn replace_ilst(&mut self, ilst: Option<&[u8]>) -> io::Result<()> {
+        let moov = self.read_moov()?;
+        let payload = with_ilst(&moov, ilst)?;
+        self.write_moov(payload)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()
+    }
+
+    fn write_moov(&mut self, payload: Vec<u8>) -> io::Result<()> {
+        let index = self.moov_index();
+        let first = self.boxes[..index].iter().rposition(|b| !is_padding(&b.kind)).map_or(0, |i| i + 1);
+        let last = index + self.boxes[index + 1..].iter().take_while(|b| is_padding(&b.kind)).count();
+        let start = self.boxes[first].offset;
+        let end = self.boxes[last].offset + self.boxes[last].len;
+        let region = end - start;
+        let moov = atom::make_box(atom::MOOV, &payload);
+        let len = moov.len() as u64;
+
+        if len == region || len + 8 <= region {
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.write_all(&moov)?;
+            let mut replacement = vec![TopBox { kind: atom::MOOV, offset: start, len }];
+            if region > len {
+                self.file.write_all(&atom::free_header(region - len).expect("free box of eight or more bytes"))?;
+                replacement.push(TopBox { kind: atom::FREE, offset: start + len, len: region - len });
+            }
+            self.boxes.splice(first..=last, replacement);
+            return Ok(());
+        }
+
+        let fragmented = self.boxes.iter().any(|b| b.kind == atom::MOOF || b.kind == atom::SIDX);
+        if fragmented && end != self.len {
+            return Err(Error::new(ErrorKind::Unsupported, "Fragmented MP4 files can't be re-tagged in place"));
+        }
+        // The new moov goes at the end before the old one is freed, so a file
+        // cut off in between still plays from the old moov.
+        let free = atom::free_header(region).ok_or_else(|| invalid("moov region too large".to_string()))?;
+        let offset = self.len;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&moov)?;
+        self.file.sync_data()?;
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.write_all(&free)?;
+        self.boxes.splice(first..=last, [TopBox { kind: atom::FREE, offset: start, len: region }]);
+        self.len += len;
+        self.boxes.push(TopBox { kind: atom::MOOV, offset, len });
+        Ok(())
+    }
+}
+
+/// `meta` is a full box in ISO files but a plain container in QuickTime ones.
+fn meta_children(data: &[u8]) -> &[u8] {
+    if data.len() >= 8 && &data[4..8] == b"hdlr" { data } else { &data[4.min(data.len())..] }
+}
+
+fn metadata_handler() -> Vec<u8> {
+    let mut body = vec![0u8; 4];
+    body.extend_from_slice(b"mdir");
+    body.extend_from_slice(b"appl");
+    body.extend_from_slice(&[0u8; 9]);
+    atom::full_box(atom::HDLR, 0, 0, &body)
+}
+
+/// Rebuilds a moov payload with a new `udta/meta/ilst`, creating the parents
+/// as needed and dropping ones left empty when the list is removed.
+fn with_ilst(moov: &[u8], ilst: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let children = atom::children(moov)?;
+    let udta = children.iter().find(|c| c.kind == atom::UDTA);
+    let udta_payload = match udta {
+        Some(udta) => with_meta(udta.data, ilst)?,
+        None => with_meta(&[], ilst)?,
+    };
+
+    let mut out = Vec::with_capacity(moov.len());
+    for child in &children {
+        if child.kind != atom::UDTA {
+            out.extend_from_slice(child.raw);
+        }
+    }
+    if !udta_payload.is_empty() {
+        out.extend(atom::make_box(atom::UDTA, &udta_payload));
+    }
+    Ok(out)
+}
+
+fn with_meta(udta: &[u8], ilst: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let children = atom::children(udta)?;
+    let meta = children.iter().find(|c| c.kind == atom::META);
+    let (is_full_box, meta_items) = match meta {
+        Some(meta) => {
+            let items = meta_children(meta.data);
+            (items.len() != meta.data.len(), atom::children(items)?)
+        }
+        None => (true, Vec::new()),
+    };
+
+    let mut meta_payload = Vec::new();
+    let mut has_handler = false;
+    for item in meta_items.iter().filter(|c| c.kind != atom::ILST) {
+        has_handler |= item.kind == atom::HDLR;
+        meta_payload.extend_from_slice(item.raw);
+    }
+    let keep_meta = ilst.is_some() || meta_items.iter().any(|c| c.kind != atom::ILST && c.kind != atom::HDLR);
+    if let Some(ilst) = ilst {
+        if !has_handler {
+            meta_payload.splice(0..0, metadata_handler());
+        }
+        meta_payload.extend(atom::make_box(atom::ILST, ilst));
+    }
+
+    let mut out = Vec::new();
+    for child in children.iter().filter(|c| c.kind != atom::META) {
+        out.extend_from_slice(child.raw);
+    }
+    if keep_meta {
+        if is_full_box {
+            out.extend(atom::full_box(atom::META, 0, 0, &meta_payload));
+        } else {
+            out.extend(atom::make_box(atom::META, &meta_payload));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    const SAMPLES: [&[u8]; 2] = [b"first chunk", b"second chunk"];
+
+    enum Part {
+        /// A moov holding an `ilst` of this many bytes.
+        Moov(usize),
+        Free(u64),
+        Mdat,
+        Moof,
+    }
+
+    fn ilst(len: usize) -> Vec<u8> {
+        vec![b'i'; len]
+    }
+
+    fn moov(ilst_len: usize, chunk: FourCc, offsets: &[u64]) -> Vec<u8> {
+        let mut table = (offsets.len() as u32).to_be_bytes().to_vec();
+        for offset in offsets {
+            if chunk == atom::CO64 {
+                table.extend_from_slice(&offset.to_be_bytes());
+            } else {
+                table.extend_from_slice(&(*offset as u32).to_be_bytes());
+            }
+        }
+        let stbl = atom::make_box(atom::STBL, &atom::full_box(chunk, 0, 0, &table));
+        let mut payload = atom::full_box(atom::MVHD, 0, 0, &[0; 96]);
+        payload.extend(atom::make_box(atom::TRAK, &atom::make_box(atom::MDIA, &atom::make_box(atom::MINF, &stbl))));
+        atom::make_box(atom::MOOV, &with_ilst(&payload, Some(&ilst(ilst_len))).unwrap())
+    }
+
+    /// The file bytes and where the media samples start.
+    fn build(parts: &[Part], chunk: FourCc, offsets: &[u64]) -> (Vec<u8>, u64) {
+        let mut bytes = atom::make_box(*b"ftyp", b"isom\0\0\0\0");
+        let mut samples = 0;
+        for part in parts {
+            match part {
+                Part::Moov(ilst_len) => bytes.extend(moov(*ilst_len, chunk, offsets)),
+                Part::Free(len) => {
+                    let mut free = atom::free_header(*len).unwrap();
+                    free.resize(*len as usize, 0);
+                    bytes.extend(free);
+                }
+                Part::Mdat => {
+                    samples = bytes.len() as u64 + 8;
+                    bytes.extend(atom::make_box(atom::MDAT, &SAMPLES.concat()));
+                }
+                Part::Moof => bytes.extend(atom::make_box(atom::MOOF, &[0; 8])),
+            }
+        }
+        (bytes, samples)
+    }
+
+    fn mp4(parts: &[Part], chunk: FourCc) -> NamedTempFile {
+        let (_, samples) = build(parts, chunk, &[0, 0]);
+        let (bytes, _) = build(parts, chunk, &[samples, samples + SAMPLES[0].len() as u64]);
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), bytes).unwrap();
+        file
+    }
+
+    fn retag(path: &Path, ilst_len: usize) -> io::Result<()> {
+        let mut file = Mp4File::edit(path)?;
+        file.replace_ilst(Some(&ilst(ilst_len)))?;
+        file.flush()
+    }
+
+    fn chunk_offsets(moov: &[u8]) -> Vec<u64> {
+        let find = |data: &[u8], kind: FourCc| {
+            atom::children(data).unwrap().into_iter().find(|c| c.kind == kind).map(|c| c.data.to_vec())
+        };
+        let trak = find(moov, atom::TRAK).unwrap();
+        let stbl = find(&find(&find(&trak, atom::MDIA).unwrap(), atom::MINF).unwrap(), atom::STBL).unwrap();
+        let (table, width) = match find(&stbl, atom::STCO) {
+            Some(table) => (table, 4),
+            None => (find(&stbl, atom::CO64).unwrap(), 8),
+        };
+        table[8..].chunks(width).map(|c| c.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)).collect()
+    }
+
+    /// Reopens the file, checks the list and that every chunk offset still
+    /// lands on its sample, and lists the top-level boxes.
+    fn check(path: &Path, ilst_len: usize) -> Vec<(FourCc, u64)> {
+        let mut file = Mp4File::open(path).unwrap();
+        assert_eq!(file.read_ilst().unwrap(), Some(ilst(ilst_len)));
+        let bytes = fs::read(path).unwrap();
+        let offsets = chunk_offsets(&file.read_moov().unwrap());
+        assert_eq!(offsets.len(), SAMPLES.len());
+        for (offset, sample) in offsets.iter().zip(SAMPLES) {
+            assert_eq!(&bytes[*offset as usize..*offset as usize + sample.len()], sample);
+        }
+        let last = file.boxes.last().unwrap();
+        assert_eq!(last.offset + last.len, bytes.len() as u64, "boxes cover the whole file");
+        file.boxes.iter().map(|b| (b.kind, b.len)).collect()
+    }
+
+    fn kinds(boxes: &[(FourCc, u64)]) -> Vec<FourCc> {
+        boxes.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    #[test]
+    fn exact_fit_absorbs_padding_on_both_sides() {
+        let fixture = mp4(&[Part::Free(8), Part::Moov(20), Part::Free(16), Part::Mdat], atom::STCO);
+        let before = fs::metadata(fixture.path()).unwrap().len();
+        retag(fixture.path(), 44).unwrap();
+
+        let boxes = check(fixture.path(), 44);
+        assert_eq!(kinds(&boxes), [*b"ftyp", atom::MOOV, atom::MDAT]);
+        assert_eq!(fs::metadata(fixture.path()).unwrap().len(), before);
+    }
+
+    #[test]
+    fn shrinking_in_place_leaves_free_space() {
+        let fixture = mp4(&[Part::Moov(20), Part::Free(16), Part::Mdat], atom::CO64);
+        retag(fixture.path(), 10).unwrap();
+
+        let boxes = check(fixture.path(), 10);
+        assert_eq!(kinds(&boxes), [*b"ftyp", atom::MOOV, atom::FREE, atom::MDAT]);
+        assert_eq!(boxes[2].1, 26);
+    }
+
+    #[test]
+    fn moov_at_the_end_is_appended_before_the_old_one_is_freed() {
+        let fixture = mp4(&[Part::Mdat, Part::Moov(20), Part::Free(8)], atom::CO64);
+        let old = Mp4File::open(fixture.path()).unwrap().boxes.clone();
+        retag(fixture.path(), 200).unwrap();
+
+        let boxes = check(fixture.path(), 200);
+        assert_eq!(kinds(&boxes), [*b"ftyp", atom::MDAT, atom::FREE, atom::MOOV]);
+        assert_eq!(boxes[2].1, old[2].len + old[3].len);
+
+        // The freed space and the moov together now take a small change in place.
+        retag(fixture.path(), 210).unwrap();
+        let boxes = check(fixture.path(), 210);
+        assert_eq!(kinds(&boxes), [*b"ftyp", atom::MDAT, atom::MOOV, atom::FREE]);
+    }
+
+    #[test]
+    fn moov_ahead_of_media_moves_to_the_end() {
+        for chunk in [atom::STCO, atom::CO64] {
+            let fixture = mp4(&[Part::Moov(20), Part::Free(8), Part::Mdat], chunk);
+            let old = Mp4File::open(fixture.path()).unwrap().boxes.clone();
+            retag(fixture.path(), 200).unwrap();
+
+            let boxes = check(fixture.path(), 200);
+            assert_eq!(kinds(&boxes), [*b"ftyp", atom::FREE, atom::MDAT, atom::MOOV]);
+            assert_eq!(boxes[1].1, old[1].len + old[2].len, "old moov and padding become one free box");
+            assert_eq!(boxes[2].1, old[3].len);
+        }
+    }
+
+    #[test]
+    fn slack_too_small_for_a_free_box_moves_the_moov() {
+        let fixture = mp4(&[Part::Moov(20), Part::Free(16), Part::Mdat], atom::STCO);
+        retag(fixture.path(), 32).unwrap();
+
+        let boxes = check(fixture.path(), 32);
+        assert_eq!(kinds(&boxes), [*b"ftyp", atom::FREE, atom::MDAT, atom::MOOV]);
+    }
+
+    #[test]
+    fn fragmented_files_are_left_alone() {
+        let fixture = mp4(&[Part::Moov(20), Part::Moof, Part::Mdat], atom::STCO);
+        let before = fs::read(fixture.path()).unwrap();
+        let error = retag(fixture.path(), 200).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(fs::read(fixture.path()).unwrap(), before);
+    }
+
+    #[test]
+    fn reads_read_only_files() {
+        let fixture = mp4(&[Part::Moov(20), Part::Mdat], atom::STCO);
+        let mut permissions = fs::metadata(fixture.path()).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(fixture.path(), permissions).unwrap();
+        assert_eq!(Mp4File::open(fixture.path()).unwrap().read_ilst().unwrap(), Some(ilst(20)));
+    }
+}
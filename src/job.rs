@@ -0,0 +1,485 @@
+use crate::nfo::Inherit;
+use crate::tags::MergePolicy;
+use log::{info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Restricts a run to one half of the work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Only {
+    /// Embed or replace the cover art and leave the tags alone. No NFO is needed.
+    Cover,
+    /// Write the tags from the NFO and leave the cover art alone.
+    Tags,
+}
+
+impl Only {
+    pub const NAMES: [&'static str; 2] = ["cover", "tags"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Only::Cover => "cover",
+            Only::Tags => "tags",
+        }
+    }
+}
+
+impl std::fmt::Display for Only {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Only {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cover" => Ok(Only::Cover),
+            "tags" => Ok(Only::Tags),
+            _ => Err(format!("unknown --only value {}, expected one of {}", s, Only::NAMES.join(", "))),
+        }
+    }
+}
+
+/// Settings shared by every video in a run.
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    pub cover_suffix: String,
+    pub output_dir: Option<PathBuf>,
+    pub delete_original: bool,
+    /// Merge into existing tags instead of clearing them first.
+    pub merge: Option<MergePolicy>,
+    /// Tag files even if their provenance stamp says they are up to date.
+    pub force: bool,
+    /// Only write the cover or only the tags.
+    pub only: Option<Only>,
+    /// How episodes combine with tvshow.nfo and season.nfo.
+    pub inherit: Inherit,
+    /// Where to look for a video's NFO and cover, in order; see [`expand`].
+    pub nfo_patterns: Vec<String>,
+    pub cover_patterns: Vec<String>,
+}
+
+/// The NFO names Kodi reads for a movie or episode.
+pub const NFO_PATTERNS: &[&str] = &["{stem}.nfo", "movie.nfo"];
+
+/// The artwork names Kodi and tinyMediaManager write, most specific first.
+pub const COVER_PATTERNS: &[&str] = &[
+    "{stem}{suffix}",
+    "{stem}-thumb",
+    "poster",
+    "folder",
+    "cover",
+    "{show}/season{season:02}-poster",
+    "{show}/poster",
+];
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            cover_suffix: "-poster".to_string(),
+            output_dir: None,
+            delete_original: false,
+            merge: None,
+            force: false,
+            only: None,
+            inherit: Inherit::default(),
+            nfo_patterns: NFO_PATTERNS.iter().map(|p| p.to_string()).collect(),
+            cover_patterns: COVER_PATTERNS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// A video with its NFO, cover and output location already resolved.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub video: PathBuf,
+    pub nfo: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+    pub output: PathBuf,
+    pub delete_original: bool,
+    pub merge: Option<MergePolicy>,
+    pub force: bool,
+    pub only: Option<Only>,
+    /// The show's tvshow.nfo and the season's season.nfo, found by walking up
+    /// from the video. Used only if the NFO turns out to be an episode.
+    pub tvshow_nfo: Option<PathBuf>,
+    pub season_nfo: Option<PathBuf>,
+    pub inherit: Inherit,
+}
+
+impl Job {
+    /// Resolves the sidecar files for `video`. Explicit `nfo`/`cover` paths win
+    /// over the ones found next to the video.
+    pub fn resolve(video: &Path, nfo: Option<&PathBuf>, cover: Option<&PathBuf>, options: &JobOptions) -> Job {
+        let (tvshow_nfo, season_nfo) = show_nfo_paths(video);
+        let show_dir = tvshow_nfo.as_deref().and_then(Path::parent).map(Path::to_path_buf);
+        let names = Names {
+            stem: video.file_stem().and_then(|s| s.to_str()).unwrap_or_default(),
+            suffix: &options.cover_suffix,
+            season: season_number(video),
+            show: show_dir.as_deref(),
+        };
+        let (tvshow_nfo, season_nfo) = match options.inherit {
+            Inherit::None => (None, None),
+            _ => {
+                if let Some(tvshow) = &tvshow_nfo {
+                    info!("  Found show NFO: {}", tvshow.display());
+                }
+                if let Some(season) = &season_nfo {
+                    info!("  Found season NFO: {}", season.display());
+                }
+                (tvshow_nfo, season_nfo)
+            }
+        };
+        Job {
+            video: video.to_path_buf(),
+            nfo: nfo_path(video, nfo, &options.nfo_patterns, &names),
+            cover: cover_path(video, cover, &options.cover_patterns, &names),
+            output: output_file_path(video.to_path_buf(), options.output_dir.as_ref())
+                .unwrap_or_else(|| video.to_path_buf()),
+            delete_original: options.delete_original,
+            merge: options.merge,
+            force: options.force,
+            only: options.only,
+            tvshow_nfo,
+            season_nfo,
+            inherit: options.inherit,
+        }
+    }
+}
+
+/// How many folders up from a video to look for tvshow.nfo, enough for
+/// `Show/Season 1/Extras/video.mkv`.
+const SHOW_DEPTH: usize = 3;
+
+/// Walks up from `video` to the nearest tvshow.nfo. A season.nfo in a
+/// folder on the way, below the show, is the episode's season.
+pub fn show_nfo_paths(video: &Path) -> (Option<PathBuf>, Option<PathBuf>) {
+    let mut season: Option<PathBuf> = None;
+    for dir in video.ancestors().skip(1).take(SHOW_DEPTH) {
+        let tvshow = dir.join("tvshow.nfo");
+        if tvshow.is_file() {
+            return (Some(tvshow), season);
+        }
+        let candidate = dir.join("season.nfo");
+        if season.is_none() && candidate.is_file() {
+            season = Some(candidate);
+        }
+    }
+    (None, None)
+}
+
+/// What `process_file` did with a job.
+#[derive(Debug, Clone)]
+pub struct FileResult {
+    pub video: PathBuf,
+    pub output: PathBuf,
+    pub nfo: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+    /// True if the file was already tagged from the same NFO and cover and
+    /// was left alone.
+    pub skipped: bool,
+}
+
+/// What a lookup pattern can refer to for one video.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Names<'a> {
+    /// `{stem}`, the video's file name without its extension.
+    pub stem: &'a str,
+    /// `{suffix}`, the cover name suffix such as `-poster`.
+    pub suffix: &'a str,
+    /// `{season}`, from an `S01E02` file name or a `Season 1` folder.
+    pub season: Option<u32>,
+    /// `{show}`, the folder holding tvshow.nfo. Expanded relative to the
+    /// folder being searched.
+    pub show: Option<&'a Path>,
+}
+
+/// Fills in the placeholders of a lookup pattern like `{stem}-thumb` or
+/// `{show}/season{season:02}-poster`, relative to `dir`. `{season}` takes a
+/// zero-padded width like the mapping templates do. Ok(None) when the video
+/// has nothing for a placeholder, such as `{season}` for a movie.
+pub fn expand(pattern: &str, dir: &Path, names: &Names) -> Result<Option<PathBuf>, String> {
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .ok_or_else(|| format!("Unclosed {{ in lookup pattern {}", pattern))? + start;
+        let placeholder = &rest[start + 1..end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => (name, width.parse::<usize>()
+                .map_err(|_| format!("Bad width in {{{}}} in lookup pattern {}", placeholder, pattern))?),
+            None => (placeholder, 0),
+        };
+        let value = match name {
+            "stem" => Some(names.stem.to_string()),
+            "suffix" => Some(names.suffix.to_string()),
+            "season" => names.season.map(|n| format!("{:0width$}", n, width = width)),
+            "show" => names.show.and_then(|show| relative_path(dir, show)).map(|p| p.display().to_string()),
+            _ => return Err(format!("Unknown placeholder {{{}}} in lookup pattern {}, expected stem, suffix, season or show", name, pattern)),
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        out.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Some(dir.join(out)))
+}
+
+/// `to` as seen from `from`, when one folder holds the other: `..` for each
+/// level up, or the rest of the path going down.
+fn relative_path(from: &Path, to: &Path) -> Option<PathBuf> {
+    if let Ok(up) = from.strip_prefix(to) {
+        let levels = up.components().count();
+        return Some(if levels == 0 { PathBuf::from(".") } else { [".."].repeat(levels).iter().collect() });
+    }
+    to.strip_prefix(from).ok().map(Path::to_path_buf)
+}
+
+/// Checks a lookup pattern from the config before any video uses it.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    let names = Names { season: Some(1), show: Some(Path::new("show")), ..Names::default() };
+    expand(pattern, Path::new(""), &names).map(|_| ())
+}
+
+/// The season in an `S01E02` file name, or else in a `Season 1` folder.
+fn season_number(video: &Path) -> Option<u32> {
+    let stem = video.file_stem()?.to_str()?.to_ascii_lowercase();
+    for (i, _) in stem.match_indices('s') {
+        let digits: String = stem[i + 1..].chars().take_while(char::is_ascii_digit).collect();
+        let after = &stem[i + 1 + digits.len()..];
+        if !digits.is_empty() && after.starts_with('e') && after[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            return digits.parse().ok();
+        }
+    }
+    let folder = video.parent()?.file_name()?.to_str()?.to_ascii_lowercase();
+    folder.strip_prefix("season")?.trim().parse().ok()
+}
+
+/// A DVD or Blu-ray folder structure. Neither has a container to tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disc {
+    Dvd,
+    Bluray,
+}
+
+impl Disc {
+    /// The disc whose `VIDEO_TS` or `BDMV` folder is `dir`.
+    pub fn of_folder(dir: &Path) -> Option<Disc> {
+        let name = dir.file_name()?.to_str()?;
+        if name.eq_ignore_ascii_case("VIDEO_TS") {
+            Some(Disc::Dvd)
+        } else if name.eq_ignore_ascii_case("BDMV") {
+            Some(Disc::Bluray)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Disc::Dvd => "DVD",
+            Disc::Bluray => "Blu-ray",
+        }
+    }
+
+    /// The NFO Kodi reads for the disc, relative to the movie folder.
+    pub fn nfo(&self) -> &'static str {
+        match self {
+            Disc::Dvd => "VIDEO_TS/VIDEO_TS.nfo",
+            Disc::Bluray => "BDMV/index.nfo",
+        }
+    }
+}
+
+/// The disc structure `path` is or is inside, with the movie folder that
+/// holds it.
+pub fn disc_root(path: &Path) -> Option<(Disc, &Path)> {
+    path.ancestors().find_map(|dir| Some((Disc::of_folder(dir)?, dir.parent()?)))
+}
+
+/// The first file the patterns name that exists, with the pattern that
+/// found it. `extensions` are tried in turn on a pattern that has none of
+/// them. Inside a disc structure the movie folder is searched as well.
+fn find_sidecar(video: &Path, patterns: &[String], names: &Names, extensions: &[&str]) -> Option<(PathBuf, String)> {
+    let mut dirs = vec![video.parent().unwrap_or(Path::new(""))];
+    if let Some((_, movie)) = disc_root(video).filter(|(_, movie)| !dirs.contains(movie)) {
+        dirs.push(movie);
+    }
+    let candidates = patterns.iter().flat_map(|p| dirs.iter().map(move |dir| (p, *dir)));
+    for (pattern, dir) in candidates {
+        let path = match expand(pattern, dir, names) {
+            Ok(Some(path)) => path,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("  {}", e);
+                continue;
+            }
+        };
+        let has_extension = path.extension().and_then(|e| e.to_str())
+            .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)));
+        let candidates: Vec<PathBuf> = if has_extension || extensions.is_empty() {
+            vec![path]
+        } else {
+            extensions.iter().map(|ext| {
+                let mut name = path.clone().into_os_string();
+                name.push(".");
+                name.push(ext);
+                PathBuf::from(name)
+            }).collect()
+        };
+        if let Some(found) = candidates.into_iter().find(|p| p.is_file()) {
+            return Some((found, pattern.clone()));
+        }
+    }
+    None
+}
+
+pub fn cover_path(video: &Path, cover_path: Option<&PathBuf>, patterns: &[String], names: &Names) -> Option<PathBuf>{
+    match cover_path {
+        Some(_)=> {
+            let new_path_name: PathBuf = cover_path.unwrap().to_path_buf();
+            if !new_path_name.extension().and_then(|e| e.to_str()).is_some_and(is_correct_image) {
+                warn!("  Incorrect type. Must be PNG, JPG, or JPEG");
+                return None
+            }
+            if !new_path_name.exists() {
+                warn!("  Cover file does not exist: {}",new_path_name.display());
+                return None;
+            }
+            info!("  Found cover file: {}",new_path_name.display());
+            Some(new_path_name)
+        }
+        None => match find_sidecar(video, patterns, names, &["jpg", "jpeg", "png"]) {
+            Some((path, pattern)) => {
+                info!("  Found cover file: {} (matched {})", path.display(), pattern);
+                Some(path)
+            }
+            None => {
+                warn!("  A cover file was not found.");
+                None
+            }
+        }
+    }
+}
+
+/// Whether `extension` is one of the cover formats, in any case.
+pub fn is_correct_image(extension: &str) -> bool {
+    ["jpg", "jpeg", "png"].iter().any(|e| e.eq_ignore_ascii_case(extension))
+}
+
+pub fn output_file_path(path: PathBuf, output_file_path: Option<&PathBuf>) -> Option<PathBuf>{
+    match output_file_path {
+        Some(_) => {
+            let filename = path.file_name();
+            let newoutput = output_file_path.unwrap().join(filename.unwrap());
+            Some(newoutput)
+        }
+        None => {
+            Some(path)
+        }
+    }
+}
+
+pub fn nfo_path(video: &Path, nfo_cli_option: Option<&PathBuf>, patterns: &[String], names: &Names) -> Option<PathBuf>{
+    match nfo_cli_option {
+        Some(_) => {
+            let nfo_check = nfo_cli_option.unwrap().to_path_buf();
+            if nfo_check.exists() {
+                info!("  Found NFO file: {}", nfo_check.display());
+                return Some(nfo_check)
+            }
+            warn!("  NFO file not found at {}", nfo_check.display());
+            None
+        }
+        None => {
+            let mut patterns = patterns.to_vec();
+            if let Some((disc, _)) = disc_root(video) {
+                patterns.push(disc.nfo().to_string());
+            }
+            match find_sidecar(video, &patterns, names, &[]) {
+                Some((path, pattern)) => {
+                    info!("  Found NFO file: {} (matched {})", path.display(), pattern);
+                    Some(path)
+                }
+                None => {
+                    match disc_root(video) {
+                        Some((disc, movie)) => warn!("  No NFO file found for {} folder: {}", disc.name(), movie.display()),
+                        None => warn!("  No NFO file found for video: {}", video.with_extension("nfo").display()),
+                    }
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn show_names(show: &Path) -> Names<'_> {
+        Names { stem: "ep", season: Some(1), show: Some(show), ..Names::default() }
+    }
+
+    #[test]
+    fn show_is_relative_to_the_searched_folder() {
+        let names = show_names(Path::new("Show"));
+        let expand = |pattern, dir| expand(pattern, Path::new(dir), &names).unwrap();
+        assert_eq!(expand("{show}/poster", "Show/Season 1"), Some(PathBuf::from("Show/Season 1/../poster")));
+        assert_eq!(expand("{show}/poster", "Show/Season 1/Extras"), Some(PathBuf::from("Show/Season 1/Extras/../../poster")));
+        assert_eq!(expand("{show}/season{season:02}-poster", "Show"), Some(PathBuf::from("Show/./season01-poster")));
+        assert_eq!(expand("{show}/poster", ""), Some(PathBuf::from("Show/poster")));
+        assert_eq!(expand("{show}/poster", "Other"), None);
+
+        let names = show_names(Path::new("/tv/Show"));
+        let path = super::expand("{show}/poster", Path::new("/tv/Show/Season 1"), &names).unwrap();
+        assert_eq!(path, Some(PathBuf::from("/tv/Show/Season 1/../poster")));
+    }
+
+    #[test]
+    fn finds_show_artwork_from_a_relative_video_path() {
+        let root = tempfile::tempdir().unwrap();
+        let season = root.path().join("Show/Season 1");
+        fs::create_dir_all(&season).unwrap();
+        for file in ["Show/tvshow.nfo", "Show/poster.jpg", "Show/season01-poster.jpg", "Show/Season 1/ep.mkv"] {
+            fs::write(root.path().join(file), b"").unwrap();
+        }
+        // The same video as a path relative to the working directory.
+        let cwd = env::current_dir().unwrap();
+        let up: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        let video = up.join(season.join("ep.mkv").strip_prefix("/").unwrap());
+        let found = |job: &Job| job.cover.as_ref().map(|c| fs::canonicalize(c).unwrap());
+
+        let job = Job::resolve(&video, None, None, &JobOptions::default());
+        assert!(job.tvshow_nfo.is_some());
+        let poster = fs::canonicalize(root.path().join("Show/season01-poster.jpg")).unwrap();
+        assert_eq!(found(&job), Some(poster));
+
+        fs::remove_file(root.path().join("Show/season01-poster.jpg")).unwrap();
+        let job = Job::resolve(&video, None, None, &JobOptions::default());
+        let poster = fs::canonicalize(root.path().join("Show/poster.jpg")).unwrap();
+        assert_eq!(found(&job), Some(poster));
+    }
+
+    #[test]
+    fn explicit_cover_needs_an_image_extension_in_any_case() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["POSTER.JPG", "poster", "poster.txt"] {
+            fs::write(dir.path().join(file), b"").unwrap();
+        }
+        let video = dir.path().join("movie.mkv");
+        let cover = |name: &str| cover_path(&video, Some(&dir.path().join(name)), &[], &Names::default());
+        assert_eq!(cover("POSTER.JPG"), Some(dir.path().join("POSTER.JPG")));
+        assert_eq!(cover("poster"), None);
+        assert_eq!(cover("poster.txt"), None);
+        assert_eq!(cover("missing.png"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! A per-run journal of what happened to each file, so an interrupted batch
+//! can pick up where it stopped. Every state change is appended as one JSON
+//! line; when reading, the last line for a video wins.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Bytes hashed from each end of a file for its fingerprint.
+const FINGERPRINT_SPAN: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum State {
+    Pending,
+    /// Being written. `temp` is a copy that is only complete once the job is
+    /// done, for backends that write to a separate output. Videos edited in
+    /// place have a `marker` from [`in_place_marker`] taken just before.
+    InProgress {
+        temp: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        marker: Option<String>,
+    },
+    Done { output: PathBuf, fingerprint: String },
+    Failed { error: String },
+    /// Cut off in a way that may have left the video damaged. Resume skips
+    /// it until `--force` is given.
+    NeedsCheck { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    video: PathBuf,
+    #[serde(flatten)]
+    state: State,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    states: HashMap<PathBuf, State>,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// The user's state folder, or else their cache folder.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::state_dir().or_else(dirs::cache_dir).map(|dir| dir.join("nfo2tags").join("journal.jsonl"))
+    }
+
+    /// Starts a new journal, discarding any previous one at `path`.
+    pub fn create(path: &Path) -> io::Result<Journal> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)?;
+        Ok(Journal { path: path.to_path_buf(), states: HashMap::new(), file: Mutex::new(file) })
+    }
+
+    /// Opens an existing journal, or starts one if there is none. Lines that
+    /// don't parse, such as one cut short by a crash, are ignored.
+    pub fn open(path: &Path) -> io::Result<Journal> {
+        let mut states = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+                    states.insert(record.video, record.state);
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { path: path.to_path_buf(), states, file: Mutex::new(file) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The state recorded when the journal was opened.
+    pub fn state(&self, video: &Path) -> Option<&State> {
+        self.states.get(video)
+    }
+
+    pub fn record(&self, video: &Path, state: State) -> io::Result<()> {
+        let mut line = serde_json::to_string(&Record { video: video.to_path_buf(), state })?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+
+    /// True if the journal says `video` was finished and its output has not
+    /// changed since.
+    pub fn is_done(&self, video: &Path) -> bool {
+        match self.state(video) {
+            Some(State::Done { output, fingerprint }) => {
+                fingerprint_file(output).is_ok_and(|current| current == *fingerprint)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A cheap identity for a large file: its length plus a SHA-256 of the first
+/// and last 64 KiB.
+pub fn fingerprint_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_be_bytes());
+
+    let mut buffer = Vec::new();
+    (&mut file).take(FINGERPRINT_SPAN).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+    if len > FINGERPRINT_SPAN {
+        buffer.clear();
+        file.seek(SeekFrom::Start(len.saturating_sub(FINGERPRINT_SPAN).max(FINGERPRINT_SPAN)))?;
+        file.take(FINGERPRINT_SPAN).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Identifies a video about to be edited in place. Edits can land between
+/// the ends [`fingerprint_file`] hashes, so the modification time is added.
+pub fn in_place_marker(path: &Path) -> io::Result<String> {
+    let modified = fs::metadata(path)?.modified()?;
+    let nanos = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    Ok(format!("{}@{}", fingerprint_file(path)?, nanos))
+}
+
+/// Cleans up after an interrupted job and says whether it is safe to run it
+/// again. A partial copy in `temp` is removed, unless the video itself is
+/// already gone, in which case the copy is all that is left. A video edited
+/// in place is only retried if it still matches the `marker` taken before
+/// the edit, i.e. the write never started. Journals without a marker fall
+/// back to asking `readable`.
+pub fn roll_back(
+    temp: Option<&Path>,
+    marker: Option<&str>,
+    video: &Path,
+    readable: impl Fn(&Path) -> bool,
+) -> io::Result<bool> {
+    match temp {
+        Some(temp) if temp != video => {
+            if !video.exists() {
+                return Ok(false);
+            }
+            if temp.exists() {
+                fs::remove_file(temp)?;
+            }
+            Ok(true)
+        }
+        _ => match marker {
+            Some(marker) => Ok(in_place_marker(video).is_ok_and(|current| current == marker)),
+            None => Ok(readable(video)),
+        },
+    }
+}
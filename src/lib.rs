@@ -0,0 +1,38 @@
+//! Writes Kodi NFO metadata and cover art into MP4 and MKV files.
+//!
+//! The `nfo2tags` binary is a thin front end over this crate: resolve a
+//! [`Job`] for each video, then hand it to [`process_file`]. Container support
+//! lives behind [`ContainerBackend`]; register extra backends on a [`Registry`]
+//! and use [`process_file_with`]. [`extract_file`] goes the other way, writing
+//! an NFO and poster from what is already embedded in a video.
+
+pub mod atom;
+pub mod backend;
+pub mod batch;
+pub mod config;
+pub mod cover;
+pub mod diff;
+pub mod ebml;
+pub mod extract;
+pub mod isobmff;
+pub mod job;
+pub mod journal;
+pub mod mapping;
+pub mod matroska;
+pub mod mkvxml;
+pub mod nfo;
+pub mod process;
+pub mod provenance;
+pub mod tags;
+pub mod verify;
+
+pub use backend::{ContainerBackend, Registry};
+pub use batch::{process_batch, resume, BatchLimits, BatchSummary};
+pub use config::{Config, Profile};
+pub use cover::{Cover, EmbeddedCover};
+pub use diff::{diff_file, diff_file_with, FileDiff};
+pub use extract::{extract_file, extract_file_with, ExtractOptions, ExtractResult};
+pub use job::{FileResult, Job, JobOptions, Only};
+pub use nfo::{Inherit, Nfo, NfoKind, Recovery};
+pub use process::{plan_file, plan_file_with, process_file, process_file_with, Plan};
+pub use tags::{MergePolicy, Tag, TagSet};
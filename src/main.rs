@@ -1,17 +1,14 @@
-mod mkvxml;
 use log::{info, warn, error};
-use serde::Deserialize;
-use clap::{value_parser, Arg, Command};
-use std::ffi::OsString;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Write};
-use std::process::Command as stdCommand;
-use std::process::Stdio;
+use nfo2tags::{diff_file_with, extract_file_with, plan_file_with, process_batch, resume, BatchLimits, ExtractOptions, Inherit, Job, JobOptions, MergePolicy, Only, Profile, Registry};
+use nfo2tags::config::Config;
+use nfo2tags::job::{self, Disc};
+use nfo2tags::journal::Journal;
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgMatches, Command};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
-use image::{open, GenericImageView};
-use std::u64;
-use indicatif::{ProgressBar,ProgressStyle};
 use walkdir::WalkDir;
 use env_logger::{Builder, Target};
 
@@ -65,38 +62,221 @@ fn main() -> io::Result<()> {
                 .long("output")
                 .value_name("output/folder/address")
                 .value_parser(value_parser!(PathBuf))
-                .help("Sets mp4's output folder. This is to accomedate the storage space issue, as the MP4 container must be recreated. ***Does not apply to MVK***"),
+                .help("Sets mp4's output folder. The video is copied there and the copy is tagged. ***Does not apply to MVK***"),
         )
         .arg(
             Arg::new("delete")
                 .short('d')
                 .long("delete")
                 .action(clap::ArgAction::SetTrue)
-                .help("Delete the OLD files after processing"),
+                .help("Delete the original mp4 after tagging a copy in the output folder"),
+        )
+        .arg(
+            Arg::new("merge")
+                .long("merge")
+                .value_name("POLICY")
+                .value_parser(MergePolicy::NAMES)
+                .help("Keep existing tags and merge the NFO into them: nfo-wins, existing-wins or fill-missing. Without it, existing tags are cleared"),
+        )
+        .arg(
+            Arg::new("only")
+                .long("only")
+                .value_name("cover|tags")
+                .value_parser(Only::NAMES)
+                .help("Only embed the cover, leaving tags alone (no NFO needed), or only write the tags, leaving the cover alone"),
+        )
+        .arg(
+            Arg::new("inherit")
+                .long("inherit")
+                .value_name("POLICY")
+                .value_parser(Inherit::NAMES)
+                .default_value("episode-wins")
+                .help("How episodes use the tvshow.nfo and season.nfo above them: episode-wins fills in what the episode lacks, show-wins lets the show override it, none ignores them"),
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .action(clap::ArgAction::SetTrue)
+                .help("Tag files even if their NFO and cover haven't changed since they were last tagged"),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+                .help("Number of files to tag at once"),
+        )
+        .arg(
+            Arg::new("mp4-jobs")
+                .long("mp4-jobs")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+                .help("Limit on MP4 files tagged at once, since copying them to --output is disk heavy"),
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .value_name("journal.jsonl")
+                .value_parser(value_parser!(PathBuf))
+                .help("Where to record each file's progress. Default is nfo2tags/journal.jsonl in the user's state or cache folder"),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(clap::ArgAction::SetTrue)
+                .help("Continue an interrupted run: skip files the journal has as done, clean up half-finished ones and retry failures"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show the tags, cover and edits for each file without changing anything"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .requires("dry-run")
+                .help("Print the dry-run plan as JSON"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("config.toml")
+                .value_parser(value_parser!(PathBuf))
+                .global(true)
+                .help("Sets the config file. Default is nfo2tags/config.toml in your config folder (~/.config on Linux)"),
+        )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help("Uses the settings of a profile from the config file. Flags given on the command line still win"),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("extract")
+                .visible_alias("tags2nfo")
+                .about("Writes an NFO and poster from the tags and cover already in MP4 or MKV files.")
+                .arg(
+                    Arg::new("video")
+                        .short('v')
+                        .long("video")
+                        .value_name("File.mp4/.mkv")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Sets the input video file. Use parent folder for multiple files.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("cover-name")
+                        .short('N')
+                        .long("cover-name")
+                        .value_name("File-poster.jpg")
+                        .help("Custom suffix for the extracted cover image")
+                        .default_value("-poster"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Overwrite NFO and cover files that already exist"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compares the tags already in MP4 or MKV files with what their NFO would write.")
+                .arg(
+                    Arg::new("video")
+                        .short('v')
+                        .long("video")
+                        .value_name("File.mp4/.mkv")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Sets the input video file. Use parent folder for multiple files.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("nfo")
+                        .short('n')
+                        .long("nfo")
+                        .value_name("File.nfo")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Sets the input NFO file"),
+                )
+                .arg(
+                    Arg::new("cover")
+                        .short('c')
+                        .long("cover")
+                        .value_name("Cover.jpg")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Sets the cover file, either jpg or png"),
+                )
+                .arg(
+                    Arg::new("cover-name")
+                        .short('N')
+                        .long("cover-name")
+                        .value_name("File-poster.jpg")
+                        .help("Custom suffix for cover images")
+                        .default_value("-poster"),
+                )
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("cover|tags")
+                        .value_parser(Only::NAMES)
+                        .help("Compare only the cover or only the tags"),
+                )
+                .arg(
+                    Arg::new("inherit")
+                        .long("inherit")
+                        .value_name("POLICY")
+                        .value_parser(Inherit::NAMES)
+                        .default_value("episode-wins")
+                        .help("How episodes use the tvshow.nfo and season.nfo above them"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the differences as JSON"),
+                ),
         )
         .get_matches();
 
-    let ffmpegtest: bool = check_for_programs("ffmpeg");
-    let mkvpropedittest: bool = check_for_programs("mkvpropedit");
-    if !ffmpegtest || !mkvpropedittest {
-        if !ffmpegtest {
-            error!("ffmpeg is not installed. Visit https://www.ffmpeg.org/ or use your package manager to install.");
-        }
-        if !mkvpropedittest {
-            error!("mkvpropedit (part of mkvtoolnix) is not installed. Visit https://mkvtoolnix.download/ or use your package manager to install.");
-        }
-        return Ok(());
+    let sub_matches = matches.subcommand().map_or(&matches, |(_, sub_matches)| sub_matches);
+    let (profile, registry) = load_settings(sub_matches)?;
+    match matches.subcommand() {
+        Some(("extract", sub_matches)) => return run_extract(sub_matches, &profile, &registry),
+        Some(("diff", sub_matches)) => return run_diff(sub_matches, &profile, &registry),
+        _ => {}
     }
 
     let video_path: &PathBuf = matches.get_one("video")
         .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,"Video File not specified"))?;
-    let cover_suffix: &String = matches.get_one("cover-name").unwrap();
-    let deletefile = matches.get_flag("delete");
+    let cover_suffix: String = setting(&matches, "cover-name", profile.cover_name.clone()).unwrap();
+    let options = JobOptions {
+        cover_suffix,
+        output_dir: setting(&matches, "output", profile.output.clone()),
+        delete_original: setting(&matches, "delete", profile.delete).unwrap_or(false),
+        merge: setting(&matches, "merge", profile.merge.map(|m| m.name().to_string()))
+            .map(|m| m.parse()).transpose()
+            .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        force: matches.get_flag("force"),
+        only: parse_only(&matches)?,
+        inherit: parse_inherit(&matches, &profile)?,
+        nfo_patterns: patterns(profile.nfo_patterns.as_ref(), job::NFO_PATTERNS)?,
+        cover_patterns: patterns(profile.cover_patterns.as_ref(), job::COVER_PATTERNS)?,
+    };
     let start_time = Instant::now();
-    let mut processed_count = 0;
-    let mut error_count = 0;
 
-    if let Some(outfolder) = matches.get_one::<PathBuf>("output") {
+    if let Some(outfolder) = &options.output_dir {
         if !outfolder.is_dir() {
             error!("Output flag must be a folder. Please try again.");
             println!("Error: Output flag must be a folder. Please try again.");
@@ -104,479 +284,274 @@ fn main() -> io::Result<()> {
         }
     }
 
-    if video_path.is_dir() {
+    let jobs: Vec<Job> = if video_path.is_dir() {
         info!("Processing directory: {}", video_path.display());
-        for entry in WalkDir::new(video_path).into_iter().filter_map(|e|e.ok()){
-            let path = entry.path();
-            if let Some(ext)= path.extension().and_then(|e|e.to_str()){
-                if ext.eq_ignore_ascii_case("mp4") || ext.eq_ignore_ascii_case("mkv") {
-                    info!("Video: {}", path.file_name().unwrap().display());
-                    let passnfo = nfo_path(path.to_path_buf(),None);
-                    let passcover = cover_path(path.to_path_buf(),None,cover_suffix.to_string());
-                    let passoutput = output_file_path(path.to_path_buf(),matches.get_one("output"));
-
-                    match process_file(path, passnfo.as_ref().map(|p|p.as_path()), passcover.as_ref().map(|p|p.as_path()),passoutput.as_ref().map(|p|p.as_path()),deletefile ) {
-                        Ok(_) => {
-                            processed_count += 1;
-                            info!("  Success: {}", path.file_name().unwrap().display());
-                        }
-                        Err(e) => {
-                            error_count += 1;
-                            warn!("  Error Processing: {}", e);
-                        }
-                    }
-                }
-            }
-        }
+        WalkDir::new(video_path).into_iter().filter_map(|e|e.ok())
+            .map(|e| e.into_path())
+            .filter(|p| is_video(p) || is_bare_disc(p))
+            .map(|path| {
+                info!("Video: {}", path.file_name().unwrap().display());
+                Job::resolve(&path, None, None, &options)
+            })
+            .collect()
     } else {
         info!("Processing single file: {}", video_path.display());
-        let coversuffix: &String = matches.get_one("cover-name").unwrap();
-        let passnfo = nfo_path(video_path.to_path_buf(),matches.get_one("nfo"));
-        let passcover = cover_path(video_path.to_path_buf(),matches.get_one("cover"),coversuffix.to_string());
-        let passoutput = output_file_path(video_path.to_path_buf(),matches.get_one("output"));
-        match process_file(video_path, passnfo.as_ref().map(|p|p.as_path()), passcover.as_ref().map(|p|p.as_path()),passoutput.as_ref().map(|p|p.as_path()),deletefile ) {
-            Ok(_) => {
-                info!("  Success: {}", passoutput.unwrap().display());
-                processed_count += 1;
+        vec![Job::resolve(video_path, matches.get_one("nfo"), matches.get_one("cover"), &options)]
+    };
+
+    if matches.get_flag("dry-run") {
+        return print_plans(&jobs, &registry, matches.get_flag("json"));
+    }
+
+    // The journal is only required when asked for; a default one that can't
+    // be written is not worth failing the run over.
+    let explicit = matches.get_one::<PathBuf>("journal").cloned();
+    let required = explicit.is_some() || matches.get_flag("resume");
+    let journal_path = explicit.or_else(Journal::default_path);
+    let (jobs, journal) = if matches.get_flag("resume") {
+        let journal_path = journal_path
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No journal to resume from; pass --journal"))?;
+        let journal = Journal::open(&journal_path)?;
+        let (jobs, skipped) = resume(jobs, &journal, &registry)?;
+        info!("Resuming from {}: {} files skipped", journal_path.display(), skipped);
+        println!("Resuming: {} files skipped, {} to process", skipped, jobs.len());
+        (jobs, Some(journal))
+    } else {
+        match journal_path.as_deref().map(|path| (path, Journal::create(path))) {
+            Some((_, Ok(journal))) => (jobs, Some(journal)),
+            Some((_, Err(e))) if required => return Err(e),
+            Some((path, Err(e))) => {
+                warn!("Could not start the journal {}: {}; running without one", path.display(), e);
+                (jobs, None)
+            }
+            None => {
+                warn!("No folder for the journal; running without one");
+                (jobs, None)
+            }
+        }
+    };
+
+    let workers = setting(&matches, "jobs", profile.jobs).unwrap();
+    let mut limits = BatchLimits { workers, ..BatchLimits::default() };
+    limits.per_backend.insert("mp4".to_string(), setting(&matches, "mp4-jobs", profile.mp4_jobs).unwrap());
+
+    let total = jobs.len();
+    let mut done = 0;
+    let summary = process_batch(&jobs, &registry, &limits, journal.as_ref(), |job, result| {
+        done += 1;
+        match result {
+            Ok(result) if result.skipped => {
+                println!("[{}/{}] Unchanged: {}", done, total, result.output.display());
+            }
+            Ok(result) => {
+                info!("  Success: {}", result.output.display());
+                println!("[{}/{}] Done: {}", done, total, result.output.display());
             }
             Err(e) => {
-                warn!("  Error Processing: {}",e);
+                warn!("  Error Processing {}: {}", job.video.display(), e);
+                println!("[{}/{}] Error: {}: {}", done, total, job.video.display(), e);
             }
         }
-    }
+    });
 
     let duration = start_time.elapsed();
     info!("Processing completed in {:?}", duration);
-    info!("Files processed: {}", processed_count);
-    if error_count > 0 {
-        warn!("  Files with errors: {}", error_count);
+    info!("Files processed: {}", summary.processed);
+    if summary.skipped > 0 {
+        info!("Files unchanged: {}", summary.skipped);
+    }
+    if summary.errors > 0 {
+        warn!("  Files with errors: {}", summary.errors);
     }
 
     Ok(())
 }
 
-fn process_file(
-    video_path: &Path,
-    nfo_path: Option<&Path>,
-    cover_path: Option<&Path>,
-    output_path: Option<&Path>,
-    deletefile: bool
-) -> io::Result<()> {
-    let mut use_nfo = true;
-    let mut nfo: Option<Nfo> = None;
-    let mut genres = String::new();
-    let mut tags = String::new();
-
-    if let Some(nfo_file_path) = nfo_path {
-        if !nfo_file_path.exists() {
-            println!("No NFO file found at provided address: {}", nfo_file_path.display());
-            use_nfo = false;
-        } else {
-            let mut nfo_file = File::open(nfo_file_path)?;
-            let mut nfo_content = String::new();
-            nfo_file.read_to_string(&mut nfo_content)?;
-            
-            let nfo_data: Nfo = quick_xml::de::from_str(&nfo_content).map_err(|e| {
-                Error::new(ErrorKind::InvalidData, format!("Failed to parse NFO: {}", e))
-            })?;
-            nfo = Some(nfo_data)
+/// Prints what each job would do without touching any file.
+fn print_plans(jobs: &[Job], registry: &Registry, json: bool) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for job in jobs {
+        match plan_file_with(job, registry) {
+            Ok(plan) if json => entries.push(serde_json::to_value(&plan)?),
+            Ok(plan) => println!("{}", plan),
+            Err(e) if json => entries.push(serde_json::json!({
+                "video": job.video,
+                "error": e.to_string(),
+            })),
+            Err(e) => println!("{} (skipped)\n  {}\n", job.video.display(), e),
         }
-    } else {
-        use_nfo = false;
     }
-
-    let mut cover_type = "image/jpeg";
-    let mut use_cover = false;
-    match cover_path {
-        Some(_) => {
-            if cover_path.unwrap().extension().and_then(|ext| ext.to_str()) == Some("png") {
-                cover_type = "image/png";
-            }
-            use_cover = true;
-        }
-        None => {
-            use_cover = false;
-        }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
     }
+    Ok(())
+}
 
-    let is_landscape = if let Some(cover_file_path) = cover_path {
-        let landscape = open(cover_file_path).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, format!("Failed to open image: {}", e))
-        })?;
-        let (width, height) = landscape.dimensions();
-        width > height
+fn run_diff(matches: &ArgMatches, profile: &Profile, registry: &Registry) -> io::Result<()> {
+    let video_path: &PathBuf = matches.get_one("video")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,"Video File not specified"))?;
+    let options = JobOptions {
+        cover_suffix: setting(matches, "cover-name", profile.cover_name.clone()).unwrap(),
+        only: parse_only(matches)?,
+        inherit: parse_inherit(matches, profile)?,
+        nfo_patterns: patterns(profile.nfo_patterns.as_ref(), job::NFO_PATTERNS)?,
+        cover_patterns: patterns(profile.cover_patterns.as_ref(), job::COVER_PATTERNS)?,
+        ..JobOptions::default()
+    };
+    let jobs: Vec<Job> = if video_path.is_dir() {
+        WalkDir::new(video_path).into_iter().filter_map(|e|e.ok())
+            .map(|e| e.into_path())
+            .filter(|p| is_video(p) || is_bare_disc(p))
+            .map(|path| Job::resolve(&path, None, None, &options))
+            .collect()
     } else {
-        false
+        vec![Job::resolve(video_path, matches.get_one("nfo"), matches.get_one("cover"), &options)]
     };
 
-    let mut output_xml_path = PathBuf::from(video_path);
-    output_xml_path.set_extension("xml");
-
-    let file_ext = video_path.extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid file extension"))?;
-
-    if !use_nfo && !use_cover {
-        warn!("  Not Processing: Due to no NFO nor cover file: {}",video_path.display());
-        return Err(Error::new(
-            ErrorKind::NotFound,
-            "NFO and Cover are missing or invalid."
-        ));
-    }
-
-    let nfo_data: Nfo = nfo.ok_or_else(|| Error::new(ErrorKind::NotFound, "NFO data not available"))?;
-    genres = nfo_data.genre.iter().map(|g| g.as_str()).collect::<Vec<_>>().join(",");
-    tags = nfo_data.tags.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(",");
-    let sanitize = |s: &str| s.replace('"', r#"\""#).replace('\n', " ").replace('\r', " ");
-    let title_metadata = format!("title={}", sanitize(&nfo_data.title));
-    let genre_metadata = format!("genre={}", genres);
-    let keywords_metadata = format!("keywords={}", tags);
-    let description_metadata = format!("description={}", sanitize(&nfo_data.plot));
-    let synopsis_metadata = format!("synopsis={}", sanitize(&nfo_data.outline));
-    let date_metadata = if !nfo_data.aired.is_empty() {
-            format!("date={}", sanitize(&nfo_data.aired))
-        } else {
-            format!("date={}", sanitize(&nfo_data.premiered))
-        };
-    let showtitle_metadata = format!("show={}", sanitize(&nfo_data.showtitle));
-    let season_metadata = format!("season_number={}", sanitize(&nfo_data.season));
-    let episode_metadata = format!("episode_id={}", sanitize(&nfo_data.episode));
-    let mkv_xml_file = format!("all:{}", output_xml_path.to_str().unwrap_or(""));
-    let mkv_cover = if is_landscape { "cover_land" } else { "cover" };
-    
-    match file_ext {
-        "mp4" => {
-            let mut working_video_path = PathBuf::new();
-            if video_path == output_path.unwrap() {
-                let mut filename: OsString = OsString::from(video_path.file_stem().unwrap());
-                filename.push(".OLD.");
-                filename.push(video_path.extension().unwrap().to_str().unwrap());
-                let mut newpath: PathBuf = video_path.to_path_buf();
-                newpath.set_file_name(filename);
-                working_video_path = newpath;
-                fs::rename(&video_path, &working_video_path)?;
-            } else {
-                working_video_path = video_path.to_path_buf();
-            }
-            
-            let mut ffmpeg_args = vec!["-nostats", "-loglevel", "0", "-i", working_video_path.to_str().unwrap_or("")];
-            
-            if use_cover {
-                if let Some(cover) = cover_path {
-                    ffmpeg_args.extend_from_slice(&["-i", cover.to_str().unwrap_or(""), "-map", "1", "-map", "0"]);
-                } else {
-                    ffmpeg_args.extend_from_slice(&["-map", "0"]);
-                }
-            } else {
-                ffmpeg_args.extend_from_slice(&["-map", "0"]);
-            }       
-
-            ffmpeg_args.extend_from_slice(&[
-                "-map_metadata", "-1",
-                "-metadata", &title_metadata,
-                "-metadata", &genre_metadata,
-                "-metadata", &keywords_metadata,
-                "-metadata", &description_metadata,
-                "-metadata", &synopsis_metadata,
-                "-metadata", &date_metadata,
-                "-metadata", &showtitle_metadata,
-                "-metadata", &season_metadata,
-                "-metadata", &episode_metadata,
-                "-c", "copy",
-            ]);
-
-            if use_cover {
-                ffmpeg_args.extend_from_slice(&["-disposition:0", "attached_pic"]);
-            }
-
-            ffmpeg_args.push(output_path.unwrap().to_str().unwrap());
-            
-            let didcomplete = run_ffmpeg_with_progress(&working_video_path.to_str().unwrap(), ffmpeg_args);
-            match didcomplete {
-                Ok(_) => {
-                    if deletefile {
-                        let _ = fs::remove_file(&working_video_path);
-                    }
-                },
-                Err(e) => { error!("FFMpeg did not complete: {}", e)}
-            }
-        },
-        "mkv" => {
-            if let Some(nfo_path) = nfo_path {
-                let _ = mkvxml::convert_to_mkv_tags(
-                    nfo_path.to_str().unwrap_or(""),
-                    output_xml_path.to_str().unwrap_or("")
-                );
-            }
-
-            let mut mkvpropedit_args = vec![
-                "--edit", "info",
-                "-s", &title_metadata,
-                video_path.to_str().unwrap_or(""),
-                "--tags", &mkv_xml_file
-            ];
-
-            if let Some(cover) = cover_path {
-                if cover.exists() {
-                    mkvpropedit_args.extend_from_slice(&[
-                        "--attachment-name", mkv_cover,
-                        "--attachment-mime-type", cover_type,
-                        "--add-attachment", cover.to_str().unwrap_or("")
-                    ]);
+    let json = matches.get_flag("json");
+    let mut entries = Vec::new();
+    let mut losing = 0;
+    for job in &jobs {
+        match diff_file_with(job, registry) {
+            Ok(diff) => {
+                if diff.loses_information() {
+                    losing += 1;
                 }
-            }
-            println!("Starting to process: {}", video_path.to_str().unwrap());
-            //todo fix these commands not running ???
-            let _ = stdCommand::new("mkvpropedit")
-                .args(&[video_path.to_str().unwrap_or(""), "--delete-attachment", "mime-type:image/jpeg"]).output();
-            let _ = stdCommand::new("mkvpropedit")
-                .args(&[video_path.to_str().unwrap_or(""), "--delete-attachment", "mime-type:image/png"]).output();
-            let _ = stdCommand::new("mkvpropedit")
-                .args(&[video_path.to_str().unwrap_or(""), "--tags", "all:"]).output();
-            let runthis = stdCommand::new("mkvpropedit").args(&mkvpropedit_args).output();
-            match runthis {
-                Ok(_) => {
-                    println!("Processing completed successfully!");
-                },
-                Err(e) => {
-                    error!("mkvpropedit error: {}",e);
-                    println!("Processing Failed!");
+                if json {
+                    let mut entry = serde_json::to_value(&diff)?;
+                    entry["loses_information"] = diff.loses_information().into();
+                    entries.push(entry);
+                } else if diff.has_changes() {
+                    println!("{}", diff);
                 }
             }
-            let _ = fs::remove_file(output_xml_path);
-        },
-        _ => error!("{} Incorrect file type. It only works with MP4 and MKV files.", file_ext),
+            Err(e) if json => entries.push(serde_json::json!({
+                "video": job.video,
+                "error": e.to_string(),
+            })),
+            Err(e) => println!("{} (skipped)\n  {}\n", job.video.display(), e),
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        println!("{} of {} files would lose information that is not in their NFO.", losing, jobs.len());
     }
     Ok(())
 }
 
-fn run_ffmpeg_with_progress(input_file: &str, mut video_args: Vec<&str>) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting to process: {}", input_file);
-    
-    let video_duration: u64 = get_video_duration(&input_file)?;
-    println!("Video duration: {} seconds", video_duration);
-    
-    let pb = ProgressBar::new(video_duration);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% ({eta}) {msg}")?
-        .progress_chars("#>-"));
-    
-    pb.set_position(0);
-    pb.set_message("Processing...");
-    
-    let input_index = video_args.iter().position(|&x| x == input_file)
-        .ok_or("Input file not found in arguments")?;
-    
-    video_args.insert(input_index + 1, "-progress");
-    video_args.insert(input_index + 2, "pipe:1");
-    
-    video_args.insert(input_index + 3, "-nostats");
-    
-    let mut cmd = stdCommand::new("ffmpeg")
-        .args(&video_args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())  // Suppress stderr to avoid interference
-        .spawn()?;
-    
-    if let Some(stdout) = cmd.stdout.take() {
-        let reader = BufReader::new(stdout);
-        
-        for line in reader.lines() {
-            match line {
-                Ok(line) => {
-
-                    if line.contains('=') {
-                        let parts: Vec<&str> = line.splitn(2, '=').collect();
-                        if parts.len() == 2 {
-                            let key = parts[0].trim();
-                            let value = parts[1].trim();
-                            
-                            match key {
-                                "out_time_us" => {
-                                    if let Ok(current_us) = value.parse::<u64>() {
-                                        let current_secs = current_us / 1_000_000;
-                                        pb.set_position(current_secs.min(video_duration));
-                                    }
-                                }
-                                "out_time" => {
-                                    if let Ok(current_secs) = parse_time_to_seconds(value) {
-                                        pb.set_position((current_secs as u64).min(video_duration));
-                                    }
-                                }
-                                "progress" => {
-                                    if value == "end" {
-                                        pb.set_position(video_duration);
-                                        break;
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                Err(_) => break,
+fn run_extract(matches: &ArgMatches, profile: &Profile, registry: &Registry) -> io::Result<()> {
+    let video_path: &PathBuf = matches.get_one("video")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,"Video File not specified"))?;
+    let options = ExtractOptions {
+        cover_suffix: setting(matches, "cover-name", profile.cover_name.clone()).unwrap(),
+        overwrite: matches.get_flag("force"),
+    };
+    let start_time = Instant::now();
+    let mut extracted_count = 0;
+    let mut error_count = 0;
+
+    let videos: Vec<PathBuf> = if video_path.is_dir() {
+        info!("Extracting from directory: {}", video_path.display());
+        WalkDir::new(video_path).into_iter().filter_map(|e|e.ok())
+            .map(|e| e.into_path())
+            .filter(|p| is_video(p))
+            .collect()
+    } else {
+        vec![video_path.clone()]
+    };
+
+    for video in videos {
+        info!("Video: {}", video.display());
+        match extract_file_with(&video, &options, registry) {
+            Ok(result) => {
+                extracted_count += 1;
+                println!("Extracted: {}", result.video.display());
+            }
+            Err(e) => {
+                error_count += 1;
+                warn!("  Error Extracting: {}", e);
             }
         }
     }
-    
-    let status = cmd.wait()?;
-    
-    if status.success() {
-        pb.set_position(video_duration);
-        pb.finish_with_message("✅ Done!");
-        println!("Processing completed successfully!");
-    } else {
-        pb.finish_with_message("❌ Failed!");
-        return Err("FFmpeg command failed".into());
+
+    info!("Extraction completed in {:?}", start_time.elapsed());
+    info!("Files extracted: {}", extracted_count);
+    if error_count > 0 {
+        warn!("  Files with errors: {}", error_count);
     }
-    
     Ok(())
 }
 
-fn parse_time_to_seconds(time_str: &str) -> Result<f64, Box<dyn std::error::Error>> {
-    let parts: Vec<&str> = time_str.split(':').collect();
-    if parts.len() != 3 {
-        return Err("Invalid time format".into());
+/// Loads the profile picked with --profile, or the config's default one, and
+/// the backends with the config's tag mappings.
+fn load_settings(matches: &ArgMatches) -> io::Result<(Profile, Registry)> {
+    let config = Config::load(matches.get_one::<PathBuf>("config").map(PathBuf::as_path))?;
+    let name = matches.get_one::<String>("profile");
+    if let Some(name) = name.or(config.default_profile.as_ref()) {
+        info!("Using profile: {}", name);
     }
-    
-    let hours: f64 = parts[0].parse()?;
-    let minutes: f64 = parts[1].parse()?;
-    let seconds: f64 = parts[2].parse()?;
-    
-    Ok(hours * 3600.0 + minutes * 60.0 + seconds)
+    let profile = config.profile(name.map(String::as_str))?;
+    let registry = config.registry(&profile)?;
+    Ok((profile, registry))
 }
 
-fn get_video_duration(input_path: &str) -> Result<u64, Box<dyn std::error::Error>> {
-    let output = stdCommand::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-show_entries",
-            "format=duration",
-            "-of",
-            "default=noprint_wrappers=1:nokey=1",
-            input_path,
-        ])
-        .stdout(Stdio::piped())
-        .spawn()?
-        .wait_with_output()?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "ffprobe failed with exit code: {}",
-            output.status
-        )
-        .into());
-    }
-    
-    let duration_str = String::from_utf8(output.stdout)?.trim().to_string();
-    let durationfloat: f64 = duration_str.parse()?;
-    let duration_secs: u64 = durationfloat.round() as u64;
-    Ok(duration_secs)
+fn parse_only(matches: &ArgMatches) -> io::Result<Option<Only>> {
+    matches.get_one::<String>("only").map(|o| o.parse()).transpose()
+        .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))
 }
 
-fn parse_timestamp(timestamp: &str) -> Result<f64, Box<dyn std::error::Error>> {
-    let parts: Vec<&str> = timestamp.split(':').collect();
-    if parts.len() != 3 {
-        return Err("Invalid timestamp format".into());
-    }
-    
-    let hours: f64 = parts[0].parse()?;
-    let minutes: f64 = parts[1].parse()?;
-    let seconds: f64 = parts[2].parse()?;
-    
-    Ok(hours * 3600.0 + minutes * 60.0 + seconds)
+fn parse_inherit(matches: &ArgMatches, profile: &Profile) -> io::Result<Inherit> {
+    setting(matches, "inherit", profile.inherit.map(|i| i.name().to_string()))
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e: String| io::Error::new(io::ErrorKind::InvalidInput, e))
 }
 
-fn cover_path(mut path: PathBuf, cover_path: Option<&PathBuf>,suffix: String) -> Option<PathBuf>{
-    match cover_path {
-        Some(_)=> {
-            let new_path_name: PathBuf = cover_path.unwrap().to_path_buf();
-            if !is_correct_image(&new_path_name.extension().unwrap().to_str().unwrap()){
-                warn!("  Incorrect type. Must be PNG, JPG, or JPEG");
-                return None
-            }
-            if !new_path_name.exists() {
-                warn!("  Cover file does not exist: {}",new_path_name.display());
-                return None;
-            }
-            info!("  Found cover file: {}",new_path_name.display());
-            return Some(new_path_name)
-        }
-        None => {
-            let cover_suffix = OsString::from(suffix);
-            let mut cover_name = OsString::from(path.file_stem().unwrap());
-            cover_name.push(cover_suffix);
-            cover_name.push(".jpg");
-            path.set_file_name(cover_name);
-            if path.exists() {
-                info!("  Found cover file: {}", path.display());
-                return Some(path)
-            }
-            path.set_extension("jpeg");
-            if path.exists() {
-                info!("  Found cover file: {}", path.display());
-                return Some(path)
-            }
-            path.set_extension("png");
-            if path.exists() {
-                info!("  Found cover file: {}", path.display());
-                return Some(path)
-            } else{
-                warn!("  A cover file was not found.");
-                return None
-            }
-        }
+/// The profile's NFO or cover lookup patterns, or the built-in ones.
+fn patterns(profile: Option<&Vec<String>>, defaults: &[&str]) -> io::Result<Vec<String>> {
+    let patterns = match profile {
+        Some(patterns) => patterns.clone(),
+        None => defaults.iter().map(|p| p.to_string()).collect(),
+    };
+    for pattern in &patterns {
+        job::check_pattern(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
     }
+    Ok(patterns)
 }
 
-fn is_correct_image(image: &str) -> bool{
-    matches!( image, "jpg" | "jpeg" | "png")
+/// A flag's value, unless it was left at its default and the profile sets it.
+fn setting<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str, profile: Option<T>) -> Option<T> {
+    match profile {
+        Some(value) if matches.value_source(id) != Some(ValueSource::CommandLine) => Some(value),
+        _ => matches.get_one::<T>(id).cloned(),
+    }
 }
 
-fn output_file_path(path: PathBuf, output_file_path: Option<&PathBuf>) -> Option<PathBuf>{
-    match output_file_path {
-        Some(_) => {
-            let filename = path.file_name();
-            let mut newoutput = output_file_path.unwrap().clone();
-            newoutput.set_file_name(filename.unwrap());
-            return Some(newoutput);
-        }
-        None => {
-            return Some(path)
-        }
-    }
+/// MP4, MKV and WebM files, leaving out `.OLD` backups made by older versions.
+fn is_video(path: &Path) -> bool {
+    let backup = path.file_stem().and_then(|s| s.to_str())
+        .is_some_and(|stem| stem.to_ascii_uppercase().ends_with(".OLD"));
+    !backup && path.extension().and_then(|e| e.to_str())
+        .is_some_and(|ext| ["mp4", "mkv", "webm"].iter().any(|x| ext.eq_ignore_ascii_case(x)))
 }
 
-fn nfo_path(mut path: PathBuf, nfo_cli_option: Option<&PathBuf>) -> Option<PathBuf>{
-    match nfo_cli_option {
-        Some(_) => {
-            let nfo_check = nfo_cli_option.unwrap().to_path_buf();
-            if nfo_check.exists() {
-                info!("  Found NFO file: {}", nfo_check.display());
-                return Some(nfo_check)
-            }
-            warn!("  NFO file not found at {}", nfo_check.display());
-            return None
-        }
-        None => {
-            path.set_extension("nfo");
-            if path.exists() {
-                info!("  Found NFO file: {}", path.display());
-                return Some(path);
-            } else {
-                warn!("  No NFO file found for video: {}", path.display());
-                return None;
-            }
-        }
-    }
+/// A DVD or Blu-ray folder without a remuxed video in it. It gets a job of
+/// its own so it is reported as unsupported rather than quietly left out.
+fn is_bare_disc(path: &Path) -> bool {
+    Disc::of_folder(path).is_some()
+        && !WalkDir::new(path).into_iter().filter_map(|e| e.ok()).any(|e| is_video(e.path()))
 }
 
-fn setup_logger() -> Result<(), io::Error> {
-    let current_exe_dir = std::env::current_exe()?
+fn exe_dir() -> io::Result<PathBuf> {
+    Ok(std::env::current_exe()?
         .parent()
         .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not get executable directory"))?
-        .to_path_buf();
+        .to_path_buf())
+}
+
+fn setup_logger() -> Result<(), io::Error> {
+    let current_exe_dir = exe_dir()?;
     
     let log_file_path = current_exe_dir.join("nfo2tags.log");
     
@@ -609,105 +584,6 @@ fn setup_logger() -> Result<(), io::Error> {
     Ok(())
 }
 
-fn check_for_programs(program_name: &str) -> bool{
-
-    match stdCommand::new(program_name).spawn() {
-        Ok(mut process) => {
-            process.kill().unwrap_or_default();
-            return true;
-        }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (), 
-        Err(_) => return false, 
-    }
-
-    let common_paths = if cfg!(windows) {
-        vec![
-            format!("C:\\Program Files\\FFmpeg\\bin\\{}.exe", program_name),
-            format!("C:\\Program Files (x86)\\FFmpeg\\bin\\{}.exe", program_name),
-            format!("C:\\Program Files\\MKVToolnix\\{}.exe", program_name), 
-            format!("C:\\Program Files (x86)\\MKVToolnix\\{}.exe", program_name),
-            format!("C:\\ffmpeg\\bin\\{}.exe", program_name),
-            format!("C:\\mkvtoolnix\\{}.exe", program_name),
-        ]
-    } else if cfg!(target_os = "macos") {
-        vec![
-            format!("/usr/local/bin/{}", program_name),
-            format!("/opt/homebrew/bin/{}", program_name),
-            format!("/Applications/MKVToolnix.app/Contents/MacOS/{}", program_name),
-        ]
-    } else { 
-        vec![
-            format!("/usr/bin/{}", program_name),
-            format!("/usr/local/bin/{}", program_name),
-            format!("/bin/{}", program_name),
-            format!("/opt/bin/{}", program_name),
-        ]
-    };
-
-    for path in common_paths {
-        if Path::new(&path).exists() {
-            return true;
-        }
-    }
-
-    false
-}
-
-#[derive(Debug, Deserialize)]
-struct Nfo {
-    title: String,
-    #[serde(default)]
-    showtitle: String,
-    #[serde(default)]
-    id: String,
-    #[serde(default)]
-    premiered: String,
-    #[serde(default)]
-    outline: String,
-    #[serde(default)]
-    plot: String,
-    #[serde(default)]
-    season: String,
-    #[serde(default)]
-    episode: String,
-    #[serde(default)]
-    aired: String,
-    #[serde(default)]
-    genre: Vec<String>,
-    #[serde(rename = "tag", default)]
-    tags: Vec<String>,
-    #[serde(rename = "uniqueid", default)]
-    unique_ids: Vec<UniqueId>,
-}
-#[derive(Debug, Deserialize)]
-struct UniqueId {
-    #[serde(rename = "@type")]
-    id_type: String,
-    #[serde(rename = "$value")]
-    value: String,
-}
-
-impl Nfo {
-    fn get_imdb_id(&self) -> Option<String> {
-        // First check if we have uniqueid elements (TV episode or newer format)
-        for unique_id in &self.unique_ids {
-            if unique_id.id_type == "imdb" {
-                return Some(unique_id.value.clone());
-            }
-        }
-
-        // Fallback to direct id field (older movie format)
-        if self.id.starts_with("tt") {
-            return Some(self.id.clone());
-        }
-        None
-    }
-
-    fn is_tv_episode(&self) -> bool {
-        !self.showtitle.is_empty() || !self.season.is_empty() || !self.episode.is_empty()
-    }
-}
-
 struct MultiWriter {
     writers: Vec<Box<dyn Write + Send + 'static>>,
 }
@@ -732,4 +608,30 @@ impl Write for MultiWriter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        Command::new("test")
+            .arg(Arg::new("cover-name").long("cover-name").default_value("-poster"))
+            .arg(Arg::new("output").long("output").value_parser(value_parser!(PathBuf)))
+            .get_matches_from(std::iter::once("test").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn command_line_wins_over_the_profile() {
+        let profile = Some("-thumb".to_string());
+        assert_eq!(setting(&matches(&[]), "cover-name", profile.clone()).as_deref(), Some("-thumb"));
+        assert_eq!(setting(&matches(&["--cover-name=-fanart"]), "cover-name", profile).as_deref(), Some("-fanart"));
+        // Naming the default on the command line still counts as choosing it.
+        assert_eq!(setting(&matches(&["--cover-name=-poster"]), "cover-name", Some("-thumb".to_string())).as_deref(), Some("-poster"));
+        assert_eq!(setting::<String>(&matches(&[]), "cover-name", None).as_deref(), Some("-poster"));
+
+        let output = Some(PathBuf::from("/profile"));
+        assert_eq!(setting(&matches(&[]), "output", output.clone()), output);
+        assert_eq!(setting(&matches(&["--output", "/cli"]), "output", output), Some(PathBuf::from("/cli")));
+        assert_eq!(setting::<PathBuf>(&matches(&[]), "output", None), None);
+    }
+}
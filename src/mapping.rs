@@ -0,0 +1,639 @@
+//! Tables saying which NFO fields go into which container tags. Each backend
+//! starts from a built-in table, and the config file can change rows or add
+//! new ones:
+//!
+//! ```toml
+//! [[mapping.mkv.fields]]
+//! tag = "SUBTITLE"
+//! value = "{showtitle} S{season:02}E{episode:02}"
+//!
+//! [[mapping.mp4.fields]]
+//! tag = "artist"
+//! value = "{director}"
+//! join = ", "
+//! ```
+
+use crate::nfo::{Nfo, NfoKind};
+use crate::tags::TagSet;
+use serde::Deserialize;
+use std::io::{self, Error, ErrorKind};
+
+/// One row of a mapping table as written in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConfig {
+    pub tag: String,
+    /// A template such as `"{title}"`. An empty value removes the tag.
+    pub value: String,
+    /// Joins the values of a multi-value field into one tag. Without it each
+    /// value gets a tag of its own.
+    pub join: Option<String>,
+}
+
+/// Changes to one backend's table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableConfig {
+    /// Which built-in table to start from, such as `legacy` for MKV.
+    pub preset: Option<String>,
+    /// Start from the built-in table. With `false` only `fields` are written.
+    pub defaults: bool,
+    pub fields: Vec<FieldConfig>,
+}
+
+impl Default for TableConfig {
+    fn default() -> Self {
+        TableConfig { preset: None, defaults: true, fields: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// An NFO field, optionally zero-padded to a width.
+    Field { path: String, width: Option<usize> },
+}
+
+/// A tag value with `{field}` placeholders. `{{` and `}}` are literal braces
+/// and `{season:02}` pads a number with zeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> io::Result<Template> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, format!("Invalid template \"{}\": {}", text, message));
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(invalid("missing }".to_string())),
+                        }
+                    }
+                    let (path, spec) = field.split_once(':').unwrap_or((&field, ""));
+                    let path = path.trim().to_ascii_lowercase();
+                    if field_values(&Nfo::default(), &path).is_none() {
+                        return Err(invalid(format!("unknown NFO field {}", path)));
+                    }
+                    let width = match spec {
+                        "" => None,
+                        spec => Some(spec.strip_prefix('0').unwrap_or(spec).parse::<usize>()
+                            .map_err(|_| invalid(format!("unknown format {}, expected a width like 02", spec)))?),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field { path, width });
+                }
+                '}' => return Err(invalid("unmatched }".to_string())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Fills in the template. A multi-value field gives one result per value.
+    /// A result with an empty field is left out, so `"S{season:02}"` writes
+    /// nothing for a movie.
+    pub fn render(&self, nfo: &Nfo) -> Vec<String> {
+        self.render_each(nfo).into_iter().flatten().collect()
+    }
+
+    /// Like [`render`](Template::render), but keeps a None in place of each
+    /// result that was left out, so results line up with the field's values.
+    pub fn render_each(&self, nfo: &Nfo) -> Vec<Option<String>> {
+        let fields: Vec<Vec<String>> = self.parts.iter().map(|part| match part {
+            Part::Field { path, .. } => field_values(nfo, path).unwrap_or_default(),
+            Part::Literal(_) => Vec::new(),
+        }).collect();
+        let count = self.parts.iter().zip(&fields)
+            .filter(|(part, _)| matches!(part, Part::Field { .. }))
+            .map(|(_, values)| values.len())
+            .max()
+            .unwrap_or(1);
+
+        let mut results = Vec::new();
+        'values: for index in 0..count {
+            let mut value = String::new();
+            for (part, values) in self.parts.iter().zip(&fields) {
+                match part {
+                    Part::Literal(text) => value.push_str(text),
+                    Part::Field { width, .. } => {
+                        let field = match values.len() {
+                            1 => &values[0],
+                            _ => match values.get(index) {
+                                Some(field) => field,
+                                None => {
+                                    results.push(None);
+                                    continue 'values;
+                                }
+                            },
+                        };
+                        if field.trim().is_empty() {
+                            results.push(None);
+                            continue 'values;
+                        }
+                        match (width, field.parse::<u64>()) {
+                            (Some(width), Ok(number)) => value.push_str(&format!("{:0width$}", number, width = *width)),
+                            _ => value.push_str(field),
+                        }
+                    }
+                }
+            }
+            results.push(Some(value));
+        }
+        results
+    }
+}
+
+/// One row of a compiled table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMapping {
+    pub tag: String,
+    pub template: Template,
+    pub join: Option<String>,
+}
+
+/// The NFO-to-tag table one backend uses.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MappingTable {
+    pub fields: Vec<FieldMapping>,
+}
+
+type Rows = &'static [(&'static str, &'static str, Option<&'static str>)];
+
+/// Matroska SimpleTag names from the tagging spec. A `COLLECTION/` or
+/// `SEASON/` prefix puts the tag at that target level instead of on the
+/// episode or movie itself, and `ACTOR.CHARACTER` nests CHARACTER in ACTOR.
+const MKV: Rows = &[
+    ("TITLE", "{title}", None),
+    ("TITLE.SORT_WITH", "{sorttitle}", None),
+    ("ORIGINAL.TITLE", "{originaltitle}", None),
+    ("COLLECTION/TITLE", "{showtitle}", None),
+    ("COLLECTION/TITLE", "{set}", None),
+    ("COLLECTION/DESCRIPTION", "{set.overview}", None),
+    ("COLLECTION/DESCRIPTION", "{tvshow.plot}", None),
+    ("COLLECTION/DATE_RELEASED", "{tvshow.premiered}", None),
+    ("COLLECTION/TOTAL_PARTS", "{tvshow.seasons}", None),
+    ("COLLECTION/IMDB", "{tvshow.uniqueid.imdb}", None),
+    ("COLLECTION/TMDB", "{tvshow.uniqueid.tmdb.path}", None),
+    ("COLLECTION/TVDB2", "{tvshow.uniqueid.tvdb.path}", None),
+    ("SEASON/TITLE", "{season.title}", None),
+    ("SEASON/DESCRIPTION", "{season.plot}", None),
+    ("SEASON/PART_NUMBER", "{season}", None),
+    ("PART_NUMBER", "{episode}", None),
+    ("CONTENT_TYPE", "{kind}", None),
+    ("DATE_RELEASED", "{releasedate}", None),
+    ("SYNOPSIS", "{plot}", None),
+    ("SUMMARY", "{outline}", None),
+    ("KEYWORDS", "{tag}", Some(",")),
+    ("GENRE", "{genre}", None),
+    ("LAW_RATING", "{mpaa}", None),
+    ("PRODUCTION_STUDIO", "{studio}", None),
+    ("DIRECTOR", "{director}", None),
+    ("WRITTEN_BY", "{credits}", None),
+    ("PRODUCER", "{producer}", None),
+    ("ACTOR", "{actor}", None),
+    ("ACTOR.CHARACTER", "{actor.role}", None),
+    ("IMDB", "{uniqueid.imdb}", None),
+    ("TMDB", "{uniqueid.tmdb.path}", None),
+    ("TVDB2", "{uniqueid.tvdb.path}", None),
+];
+
+/// The names nfo2tags wrote before it followed the spec, all on one level.
+const MKV_LEGACY: Rows = &[
+    ("TITLE", "{title}", None),
+    ("SUBTITLE", "{showtitle}", None),
+    ("SEASON", "{season}", None),
+    ("EPISODE", "{episode}", None),
+    ("ORIGINALTITLE", "{originaltitle}", None),
+    ("YEAR", "{year}", None),
+    ("TAGLINE", "{tagline}", None),
+    ("RUNTIME", "{runtime}", None),
+    ("MPAA", "{mpaa}", None),
+    ("CERTIFICATION", "{certification}", None),
+    ("TMDB", "{uniqueid.tmdb}", None),
+    ("COUNTRY", "{country}", None),
+    ("PREMIERED", "{premiered}", None),
+    ("STUDIO", "{studio}", None),
+    ("GENRE", "{genre}", None),
+    ("IMDB", "{uniqueid.imdb}", None),
+    ("DESCRIPTION", "{plot}", None),
+    ("SUMMARY", "{outline}", None),
+    ("Collection Name", "{set}", None),
+    ("Collection Overview", "{set.overview}", None),
+    ("Director", "{director}", Some(",")),
+    ("WRITER", "{credits}", Some(",")),
+    ("Actor", "{actor}", Some(",")),
+];
+
+/// MP4 tag names from the ATOMS table in the MP4 backend, covering what
+/// Apple TV, Infuse and Plex read.
+const MP4: Rows = &[
+    ("title", "{episodes.title}", Some(" / ")),
+    ("sort_name", "{sorttitle}", None),
+    ("media_type", "{kind}", None),
+    ("hd_video", "{resolution}", None),
+    ("genre", "{genre}", Some(",")),
+    ("keywords", "{tag}", Some(",")),
+    ("description", "{plot}", None),
+    ("synopsis", "{episodes.plot}", Some("\n\n")),
+    ("date", "{releasedate}", None),
+    ("artist", "{actor}", Some(", ")),
+    ("album_artist", "{actor}", Some(", ")),
+    ("show", "{showtitle}", None),
+    ("sort_show", "{showtitle}", None),
+    ("network", "{studio}", Some(", ")),
+    ("season_number", "{season}", None),
+    ("episode_sort", "{episode}", None),
+    ("episode_id", "{episodecode}", None),
+    ("----:com.apple.iTunes:iTunEXTC", "{mpaa.itunes}", None),
+    ("----:com.apple.iTunes:IMDB", "{uniqueid.imdb}", None),
+    ("----:com.apple.iTunes:TMDB", "{uniqueid.tmdb}", None),
+    ("----:com.apple.iTunes:TVDB", "{uniqueid.tvdb}", None),
+];
+
+/// The MP4 tags nfo2tags wrote before the iTunes atoms above.
+const MP4_LEGACY: Rows = &[
+    ("title", "{title}", None),
+    ("genre", "{genre}", Some(",")),
+    ("keywords", "{tag}", Some(",")),
+    ("description", "{plot}", None),
+    ("synopsis", "{outline}", None),
+    ("date", "{releasedate}", None),
+    ("show", "{showtitle}", None),
+    ("season_number", "{season}", None),
+    ("episode_id", "{episode}", None),
+];
+
+/// Built-in tables by backend and name. Each backend's first one is its
+/// default.
+const PRESETS: &[(&str, &str, Rows)] = &[
+    ("mkv", "matroska", MKV),
+    ("mkv", "legacy", MKV_LEGACY),
+    ("mp4", "itunes", MP4),
+    ("mp4", "legacy", MP4_LEGACY),
+];
+
+impl MappingTable {
+    pub fn mkv() -> MappingTable {
+        MappingTable::built_in(MKV)
+    }
+
+    pub fn mp4() -> MappingTable {
+        MappingTable::built_in(MP4)
+    }
+
+    /// A built-in table for `backend` by name, or its default one.
+    pub fn preset(backend: &str, name: Option<&str>) -> io::Result<MappingTable> {
+        let mut presets = PRESETS.iter().filter(|(b, _, _)| *b == backend);
+        let found = match name {
+            Some(name) => presets.find(|(_, n, _)| n.eq_ignore_ascii_case(name)),
+            None => presets.next(),
+        };
+        match found {
+            Some((_, _, rows)) => Ok(MappingTable::built_in(rows)),
+            None => {
+                let known: Vec<&str> = PRESETS.iter().filter(|(b, _, _)| *b == backend).map(|(_, n, _)| *n).collect();
+                Err(Error::new(ErrorKind::InvalidInput, format!(
+                    "No {} mapping preset named {}, expected one of: {}", backend, name.unwrap_or_default(), known.join(", "))))
+            }
+        }
+    }
+
+    fn built_in(rows: Rows) -> MappingTable {
+        let fields = rows.iter().map(|(tag, value, join)| FieldMapping {
+            tag: tag.to_string(),
+            template: Template::parse(value).expect("built-in templates are valid"),
+            join: join.map(String::from),
+        }).collect();
+        MappingTable { fields }
+    }
+
+    /// Applies config rows to this table. A row replaces every row with the
+    /// same tag name (ignoring case), taking the place of the first, and
+    /// further config rows with that name are added after it. An empty value
+    /// removes them all, and new names are added at the end.
+    pub fn customize(mut self, config: &TableConfig) -> io::Result<MappingTable> {
+        if !config.defaults {
+            self.fields.clear();
+        }
+        let mut customized: Vec<&str> = Vec::new();
+        for row in &config.fields {
+            let same = |f: &FieldMapping| f.tag.eq_ignore_ascii_case(&row.tag);
+            if row.value.is_empty() {
+                self.fields.retain(|f| !same(f));
+                continue;
+            }
+            let field = FieldMapping { tag: row.tag.clone(), template: Template::parse(&row.value)?, join: row.join.clone() };
+            if customized.iter().any(|tag| tag.eq_ignore_ascii_case(&row.tag)) {
+                let after = self.fields.iter().rposition(same).map_or(self.fields.len(), |i| i + 1);
+                self.fields.insert(after, field);
+                continue;
+            }
+            customized.push(&row.tag);
+            match self.fields.iter().position(same) {
+                Some(first) => {
+                    self.fields[first] = field;
+                    let rest = self.fields.split_off(first + 1);
+                    self.fields.extend(rest.into_iter().filter(|f| !same(f)));
+                }
+                None => self.fields.push(field),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Renders every row. A row named `PARENT.CHILD` goes with an earlier
+    /// `PARENT` row value by value, so each `ACTOR.CHARACTER` follows the
+    /// ACTOR it belongs to.
+    pub fn apply(&self, nfo: &Nfo) -> TagSet {
+        let mut entries: Vec<(&str, String, TagSet)> = Vec::new();
+        // Which entry each value of a row became, for rows nested under it.
+        let mut placed: Vec<(&str, Vec<Option<usize>>)> = Vec::new();
+        for field in &self.fields {
+            let values = field.template.render_each(nfo);
+            let parent = field.tag.rsplit_once('.')
+                .and_then(|(parent, _)| placed.iter().rev().find(|(tag, _)| tag.eq_ignore_ascii_case(parent)));
+            if let Some((_, positions)) = parent {
+                for (position, value) in positions.iter().zip(values) {
+                    if let (Some(position), Some(value)) = (position, value) {
+                        entries[*position].2.push(&field.tag, &value);
+                    }
+                }
+                continue;
+            }
+            let positions = match &field.join {
+                Some(separator) => {
+                    let values: Vec<String> = values.into_iter().flatten().collect();
+                    entries.push((&field.tag, values.join(separator), TagSet::new()));
+                    vec![Some(entries.len() - 1)]
+                }
+                None => values.into_iter().map(|value| value.map(|value| {
+                    entries.push((&field.tag, value, TagSet::new()));
+                    entries.len() - 1
+                })).collect(),
+            };
+            placed.push((&field.tag, positions));
+        }
+
+        let mut tags = TagSet::new();
+        for (tag, value, nested) in entries {
+            tags.push(tag, &value);
+            for tag in nested.iter() {
+                tags.push(&tag.name, &tag.value);
+            }
+        }
+        tags
+    }
+}
+
+/// The values of an NFO field by its element name. `set.overview`,
+/// `actor.role`, `uniqueid.<type>` and `rating.<name>` reach into nested
+/// elements and attributes; `releasedate` is `aired` or else `premiered`.
+/// `kind` says what the NFO describes, and `uniqueid.tmdb.path` and
+/// `uniqueid.tvdb.path` are ids in their site's URL form, like `movie/603`.
+/// `resolution` is SD, 720p, 1080p or 2160p from the stream details, and
+/// `mpaa.itunes` is the rating in iTunes' `mpaa|PG-13|300|` form.
+/// `episodecode` is `S01E02`, or `S01E02-E03` for a multi-episode file.
+/// `seasons` counts a show's numbered `<namedseason>`s, or else takes its
+/// `<season>`, skipping specials and unknown (zero or negative) counts.
+/// `tvshow.<field>` and `season.<field>` read an episode's tvshow.nfo and
+/// season.nfo, and `episodes.<field>` gives the field of every episode in a
+/// multi-episode file. Returns None for names that aren't NFO fields.
+pub fn field_values(nfo: &Nfo, path: &str) -> Option<Vec<String>> {
+    if let Some(path) = path.strip_prefix("episodes.") {
+        let values: Option<Vec<Vec<String>>> = nfo.episodes().map(|episode| field_values(episode, path)).collect();
+        return values.map(|values| values.concat());
+    }
+    for (prefix, parent) in [("tvshow.", &nfo.tvshow), ("season.", &nfo.season_nfo)] {
+        if let Some(path) = path.strip_prefix(prefix) {
+            return field_values(parent.as_deref().unwrap_or(&Nfo::default()), path);
+        }
+    }
+    let text = |value: &str| vec![value.to_string()];
+    let number = |value: Option<u32>| value.map(|v| vec![v.to_string()]).unwrap_or_default();
+    let list = |values: &[String]| values.to_vec();
+    let values = match path {
+        "title" => text(&nfo.title),
+        "originaltitle" => text(&nfo.originaltitle),
+        "sorttitle" => text(&nfo.sorttitle),
+        "showtitle" => text(&nfo.showtitle),
+        "outline" => text(&nfo.outline),
+        "plot" => text(&nfo.plot),
+        "tagline" => text(&nfo.tagline),
+        "mpaa" => text(&nfo.mpaa),
+        "certification" => text(&nfo.certification),
+        "premiered" => text(&nfo.premiered),
+        "aired" => text(&nfo.aired),
+        "releasedate" => text(nfo.release_date()),
+        "status" => text(&nfo.status),
+        "code" => text(&nfo.code),
+        "trailer" => text(&nfo.trailer),
+        "dateadded" => text(&nfo.dateadded),
+        "album" => text(&nfo.album),
+        "year" => number(nfo.year),
+        "runtime" => number(nfo.runtime),
+        "season" => number(nfo.season),
+        "episode" => number(nfo.episode),
+        "track" => number(nfo.track),
+        "top250" => number(nfo.top250),
+        "userrating" => nfo.userrating.map(|r| vec![r.to_string()]).unwrap_or_default(),
+        "genre" => list(&nfo.genres),
+        "tag" => list(&nfo.tags),
+        "country" => list(&nfo.countries),
+        "studio" => list(&nfo.studios),
+        "director" => list(&nfo.directors),
+        "credits" => list(&nfo.credits),
+        "artist" => list(&nfo.artists),
+        "actor" | "actor.name" => nfo.actors_in_order().iter().map(|a| a.name.clone()).collect(),
+        "actor.role" => nfo.actors_in_order().iter().map(|a| a.role.clone()).collect(),
+        "producer" | "producer.name" => nfo.producers.iter().map(|p| p.name.clone()).collect(),
+        "set" | "set.name" => nfo.collection().map(|s| text(s.name())).unwrap_or_default(),
+        "set.overview" => nfo.collection().map(|s| text(&s.overview)).unwrap_or_default(),
+        "kind" => text(kind_name(nfo)),
+        "uniqueid.tmdb.path" => id_path(nfo.tmdb_id(), match nfo.kind {
+            NfoKind::Movie if !nfo.is_tv_episode() => "movie",
+            NfoKind::TvShow => "tv",
+            _ => "",
+        }),
+        "uniqueid.tvdb.path" => id_path(nfo.tvdb_id(), match nfo.kind {
+            NfoKind::TvShow => "series",
+            _ if nfo.is_tv_episode() => "episodes",
+            _ => "movies",
+        }),
+        "episodecode" => episode_code(nfo).map(|c| vec![c]).unwrap_or_default(),
+        "seasons" => number(season_count(nfo)),
+        "resolution" => resolution(nfo).map(text).unwrap_or_default(),
+        "mpaa.itunes" => itunes_rating(&nfo.mpaa).map(|r| vec![r]).unwrap_or_default(),
+        "rating" => nfo.default_rating().and_then(|r| r.value).map(|v| vec![v.to_string()]).unwrap_or_default(),
+        _ => {
+            if let Some(id_type) = path.strip_prefix("uniqueid.") {
+                let id = match id_type {
+                    "imdb" => nfo.imdb_id(),
+                    "tmdb" => nfo.tmdb_id(),
+                    "tvdb" => nfo.tvdb_id(),
+                    other => nfo.unique_id(other),
+                };
+                id.map(text).unwrap_or_default()
+            } else if let Some(name) = path.strip_prefix("rating.") {
+                nfo.ratings.ratings.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+                    .and_then(|r| r.value).map(|v| vec![v.to_string()]).unwrap_or_default()
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(values)
+}
+
+fn kind_name(nfo: &Nfo) -> &'static str {
+    match nfo.kind {
+        NfoKind::TvShow => "TV Show",
+        NfoKind::MusicVideo => "Music Video",
+        _ if nfo.is_tv_episode() => "Episode",
+        _ => "Movie",
+    }
+}
+
+/// `id` under `section`, or nothing when either is missing.
+fn id_path(id: Option<&str>, section: &str) -> Vec<String> {
+    match id {
+        Some(id) if !section.is_empty() => vec![format!("{}/{}", section, id)],
+        _ => Vec::new(),
+    }
+}
+
+/// The first to the last episode in the file, leaving out the season when
+/// both are in the same one.
+fn episode_code(nfo: &Nfo) -> Option<String> {
+    let code = |episode: &Nfo| Some((episode.season?, episode.episode?));
+    let (season, first) = code(nfo)?;
+    let mut out = format!("S{:02}E{:02}", season, first);
+    match nfo.more_episodes.last().and_then(code) {
+        Some((last_season, last)) if last_season == season => out.push_str(&format!("-E{:02}", last)),
+        Some((last_season, last)) => out.push_str(&format!("-S{:02}E{:02}", last_season, last)),
+        None => {}
+    }
+    Some(out)
+}
+
+fn season_count(nfo: &Nfo) -> Option<u32> {
+    let mut named: Vec<u32> = nfo.named_seasons.iter().filter_map(|s| s.number).filter(|n| *n > 0).collect();
+    named.sort_unstable();
+    named.dedup();
+    match named.len() {
+        0 => nfo.season.filter(|n| *n > 0),
+        count => Some(count as u32),
+    }
+}
+
+/// The resolution class of the first video stream in `<fileinfo>`.
+fn resolution(nfo: &Nfo) -> Option<&'static str> {
+    let video = nfo.fileinfo.as_ref()?.streamdetails.video.first()?;
+    let (width, height) = (video.width.unwrap_or(0), video.height.unwrap_or(0));
+    Some(match (width, height) {
+        (0, 0) => return None,
+        (w, h) if w >= 3200 || h >= 2000 => "2160p",
+        (w, h) if w >= 1600 || h >= 1000 => "1080p",
+        (w, h) if w >= 1200 || h >= 700 => "720p",
+        _ => "SD",
+    })
+}
+
+/// US ratings with the codes iTunes sorts them by.
+const ITUNES_RATINGS: &[(&str, &str, u32)] = &[
+    ("mpaa", "G", 100),
+    ("mpaa", "PG", 200),
+    ("mpaa", "PG-13", 300),
+    ("mpaa", "R", 400),
+    ("mpaa", "NC-17", 500),
+    ("us-tv", "TV-Y", 100),
+    ("us-tv", "TV-Y7", 200),
+    ("us-tv", "TV-G", 300),
+    ("us-tv", "TV-PG", 400),
+    ("us-tv", "TV-14", 500),
+    ("us-tv", "TV-MA", 600),
+];
+
+/// An `<mpaa>` value like `Rated PG-13` or `US:TV-MA` as iTunes writes it.
+/// Ratings from other countries have no iTunes form here.
+fn itunes_rating(mpaa: &str) -> Option<String> {
+    let mpaa = mpaa.trim();
+    let rating = mpaa.strip_prefix("US:").or_else(|| mpaa.strip_prefix("Rated ")).unwrap_or(mpaa).trim();
+    let system = if rating.to_ascii_uppercase().starts_with("TV-") { "us-tv" } else { "mpaa" };
+    ITUNES_RATINGS.iter()
+        .find(|(s, name, _)| *s == system && name.eq_ignore_ascii_case(rating))
+        .map(|(system, name, code)| format!("{}|{}|{}|", system, name, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(tag: &str, value: &str) -> FieldConfig {
+        FieldConfig { tag: tag.to_string(), value: value.to_string(), join: None }
+    }
+
+    fn rows<'a>(table: &'a MappingTable, tag: &str) -> Vec<&'a Template> {
+        table.fields.iter().filter(|f| f.tag.eq_ignore_ascii_case(tag)).map(|f| &f.template).collect()
+    }
+
+    #[test]
+    fn customize_replaces_every_row_with_the_name() {
+        let original = MappingTable::mkv();
+        assert_eq!(rows(&original, "COLLECTION/TITLE").len(), 2);
+        let first = original.fields.iter().position(|f| f.tag == "COLLECTION/TITLE").unwrap();
+
+        let config = TableConfig { fields: vec![row("collection/title", "{set}")], ..TableConfig::default() };
+        let table = original.clone().customize(&config).unwrap();
+        assert_eq!(rows(&table, "COLLECTION/TITLE"), [&Template::parse("{set}").unwrap()]);
+        assert_eq!(table.fields[first].tag, "collection/title", "takes the place of the first row");
+        assert_eq!(table.fields.len(), original.fields.len() - 1);
+
+        let config = TableConfig { fields: vec![row("COLLECTION/TITLE", "")], ..TableConfig::default() };
+        assert!(rows(&original.clone().customize(&config).unwrap(), "COLLECTION/TITLE").is_empty());
+
+        let config = TableConfig {
+            fields: vec![row("COLLECTION/TITLE", "{showtitle}"), row("COLLECTION/TITLE", "{set}"), row("NEW", "{title}")],
+            ..TableConfig::default()
+        };
+        let table = original.customize(&config).unwrap();
+        let expected = [Template::parse("{showtitle}").unwrap(), Template::parse("{set}").unwrap()];
+        assert_eq!(rows(&table, "COLLECTION/TITLE"), expected.iter().collect::<Vec<_>>());
+        assert_eq!(table.fields[first + 1].tag, "COLLECTION/TITLE");
+        assert_eq!(table.fields.last().unwrap().tag, "NEW");
+    }
+
+    #[test]
+    fn episode_code_spans_a_multi_episode_file() {
+        let episode = |season, episode| Nfo { season: Some(season), episode: Some(episode), ..Nfo::default() };
+        let code = |nfo: &Nfo| field_values(nfo, "episodecode").unwrap();
+        let mut nfo = episode(1, 1);
+        assert_eq!(code(&nfo), ["S01E01"]);
+        nfo.more_episodes = vec![episode(1, 2), episode(1, 3)];
+        assert_eq!(code(&nfo), ["S01E01-E03"]);
+        nfo.more_episodes = vec![episode(2, 1)];
+        assert_eq!(code(&nfo), ["S01E01-S02E01"]);
+        assert!(code(&Nfo::default()).is_empty());
+    }
+}
@@ -0,0 +1,645 @@
+//! In-place editing of Matroska top-level metadata elements (Info, Tags,
+//! Attachments). Changed elements are written back over their old location
+//! when they fit, using Void padding for the slack; otherwise the old copy is
+//! voided, the new one is appended to the segment and the SeekHead is updated.
+
+use crate::ebml::{self, Header};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Space kept free behind a SeekHead when filling the Void that follows it.
+const SEEK_HEAD_RESERVE: u64 = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct TopLevel {
+    id: u32,
+    offset: u64,
+    len: u64,
+}
+
+pub struct MatroskaFile {
+    file: File,
+    segment_data_start: u64,
+    segment_size_pos: u64,
+    segment_size_len: usize,
+    segment_size_known: bool,
+    segment_end: u64,
+    elements: Vec<TopLevel>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl MatroskaFile {
+    /// Opens a file for reading, so read-only files and mounts work too.
+    pub fn open(path: &Path) -> io::Result<MatroskaFile> {
+        MatroskaFile::load(File::open(path)?, path)
+    }
+
+    /// Opens a file to be edited in place.
+    pub fn edit(path: &Path) -> io::Result<MatroskaFile> {
+        MatroskaFile::load(OpenOptions::new().read(true).write(true).open(path)?, path)
+    }
+
+    fn load(file: File, path: &Path) -> io::Result<MatroskaFile> {
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file.try_clone()?);
+
+        let ebml = ebml::read_header(&mut reader)?;
+        let ebml_size = ebml.size.filter(|_| ebml.id == ebml::EBML_HEADER)
+            .ok_or_else(|| invalid(format!("{} is not an EBML file", path.display())))?;
+        let mut ebml_data = vec![0u8; ebml_size as usize];
+        reader.read_exact(&mut ebml_data)?;
+        let doc_type = ebml::children(&ebml_data)?.iter()
+            .find(|c| c.id == ebml::DOC_TYPE)
+            .map(|c| ebml::read_string(c.data))
+            .unwrap_or_default();
+        if doc_type != "matroska" && doc_type != "webm" {
+            return Err(invalid(format!("Unsupported EBML document type '{}'", doc_type)));
+        }
+
+        let segment_size_pos = ebml.header_len as u64 + ebml_size + ebml::encode_id(ebml::SEGMENT).len() as u64;
+        let segment = ebml::read_header(&mut reader)?;
+        if segment.id != ebml::SEGMENT {
+            return Err(invalid(format!("{} has no Matroska segment", path.display())));
+        }
+        let segment_data_start = ebml.header_len as u64 + ebml_size + segment.header_len as u64;
+        let segment_end = match segment.size {
+            Some(size) => segment_data_start + size,
+            None => file_len,
+        };
+        if segment_end > file_len {
+            return Err(invalid(format!("{} is truncated", path.display())));
+        }
+
+        let mut elements = Vec::new();
+        let mut pos = segment_data_start;
+        while pos < segment_end {
+            reader.seek(SeekFrom::Start(pos))?;
+            let header: Header = ebml::read_header(&mut reader)?;
+            let size = header.size.ok_or_else(|| {
+                invalid("Unknown-size top-level elements (live streams) can't be edited in place".to_string())
+            })?;
+            let len = header.header_len as u64 + size;
+            if pos + len > segment_end {
+                return Err(invalid(format!("Element {:X} runs past the end of the segment", header.id)));
+            }
+            elements.push(TopLevel { id: header.id, offset: pos, len });
+            pos += len;
+        }
+
+        Ok(MatroskaFile {
+            file,
+            segment_data_start,
+            segment_size_pos,
+            segment_size_len: segment.header_len - ebml::encode_id(ebml::SEGMENT).len(),
+            segment_size_known: segment.size.is_some(),
+            segment_end,
+            elements,
+        })
+    }
+
+    /// Payload of the first top-level element with `id`.
+    pub fn read_element(&mut self, id: u32) -> io::Result<Option<Vec<u8>>> {
+        let Some(element) = self.elements.iter().find(|e| e.id == id).copied() else {
+            return Ok(None);
+        };
+        self.file.seek(SeekFrom::Start(element.offset))?;
+        let header = ebml::read_header(&mut self.file)?;
+        let mut data = vec![0u8; header.size.unwrap_or(0) as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Replaces the first top-level element with `id` by `payload`, or removes
+    /// it when `payload` is `None`.
+    pub fn replace_element(&mut self, id: u32, payload: Option<&[u8]>) -> io::Result<()> {
+        let existing = self.elements.iter().position(|e| e.id == id);
+        let offset = match payload {
+            Some(payload) => Some(self.place(existing, id, &ebml::element(id, payload))?),
+            None => match existing {
+                Some(index) => {
+                    self.remove(index)?;
+                    None
+                }
+                None => return Ok(()),
+            },
+        };
+        self.set_seek_entry(id, offset)
+    }
+
+    /// Like [`replace_element`](Self::replace_element), but only writes where
+    /// the element already sits or into a Void ahead of the first Cluster, for
+    /// elements players expect before the media data. Returns false, leaving
+    /// the file untouched, when there is no room.
+    pub fn replace_before_clusters(&mut self, id: u32, payload: &[u8]) -> io::Result<bool> {
+        let bytes = ebml::element(id, payload);
+        let existing = self.elements.iter().position(|e| e.id == id);
+        if let Some(index) = existing {
+            if self.available(index) >= bytes.len() as u64 && self.write_in_place(index, id, &bytes)? {
+                let offset = self.elements[index].offset;
+                self.set_seek_entry(id, Some(offset))?;
+                return Ok(true);
+            }
+        }
+
+        let first_cluster = self.elements.iter().position(|e| e.id == ebml::CLUSTER).unwrap_or(self.elements.len());
+        let Some(index) = self.find_void(bytes.len() as u64).filter(|i| *i < first_cluster) else {
+            return Ok(false);
+        };
+        let old_offset = existing.map(|i| self.elements[i].offset);
+        let offset = self.write_into_void(index, id, &bytes)?;
+        if let Some(old) = old_offset.and_then(|o| self.index_at(o)) {
+            self.void_out(old)?;
+        }
+        self.set_seek_entry(id, Some(offset))?;
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()
+    }
+
+    fn read_at(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.elements[index].offset))?;
+        let header = ebml::read_header(&mut self.file)?;
+        let mut data = vec![0u8; header.size.unwrap_or(0) as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn index_at(&self, offset: u64) -> Option<usize> {
+        self.elements.iter().position(|e| e.offset == offset)
+    }
+
+    /// Writes a complete element, reusing `existing`'s spot when it fits, and
+    /// returns the offset it landed at.
+    fn place(&mut self, existing: Option<usize>, id: u32, bytes: &[u8]) -> io::Result<u64> {
+        if let Some(index) = existing {
+            if self.available(index) >= bytes.len() as u64 && self.write_in_place(index, id, bytes)? {
+                return Ok(self.elements[index].offset);
+            }
+            if self.is_tail(index)? {
+                self.truncate_to(index)?;
+                return self.append(id, bytes);
+            }
+            self.void_out(index)?;
+        }
+
+        match self.find_void(bytes.len() as u64) {
+            Some(index) => self.write_into_void(index, id, bytes),
+            None => self.append(id, bytes),
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> io::Result<()> {
+        if self.is_tail(index)? {
+            self.truncate_to(index)
+        } else {
+            self.void_out(index)
+        }
+    }
+
+    /// Bytes usable at element `index`: itself plus any Voids right after it.
+    fn available(&self, index: usize) -> u64 {
+        self.elements[index..].iter().enumerate()
+            .take_while(|(i, e)| *i == 0 || e.id == ebml::VOID)
+            .map(|(_, e)| e.len)
+            .sum()
+    }
+
+    /// Writes `bytes` at element `index`, absorbing following Voids and padding
+    /// the rest with a new Void. Returns false if the slack can't hold a Void.
+    fn write_in_place(&mut self, index: usize, id: u32, bytes: &[u8]) -> io::Result<bool> {
+        let available = self.available(index);
+        let len = bytes.len() as u64;
+        let slack = available - len;
+        if slack == 1 {
+            return Ok(false);
+        }
+        let offset = self.elements[index].offset;
+        let absorbed = self.elements[index..].iter().enumerate()
+            .take_while(|(i, e)| *i == 0 || e.id == ebml::VOID)
+            .count();
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)?;
+        let mut replacement = vec![TopLevel { id, offset, len }];
+        if slack > 0 {
+            let void = ebml::void_header(slack).expect("slack of two or more bytes");
+            self.file.write_all(&void)?;
+            replacement.push(TopLevel { id: ebml::VOID, offset: offset + len, len: slack });
+        }
+        self.elements.splice(index..index + absorbed, replacement);
+        Ok(true)
+    }
+
+    fn void_out(&mut self, index: usize) -> io::Result<()> {
+        let element = self.elements[index];
+        let void = ebml::void_header(element.len)
+            .ok_or_else(|| invalid("Element too small to void".to_string()))?;
+        self.file.seek(SeekFrom::Start(element.offset))?;
+        self.file.write_all(&void)?;
+        self.elements[index].id = ebml::VOID;
+        Ok(())
+    }
+
+    /// True if everything from element `index` to the end of the file is that
+    /// element and Voids, so it can be rewritten by truncating.
+    fn is_tail(&self, index: usize) -> io::Result<bool> {
+        let element = self.elements[index];
+        Ok(element.offset + self.available(index) == self.segment_end
+            && self.segment_end == self.file.metadata()?.len())
+    }
+
+    fn truncate_to(&mut self, index: usize) -> io::Result<()> {
+        let offset = self.elements[index].offset;
+        self.file.set_len(offset)?;
+        self.elements.truncate(index);
+        self.segment_end = offset;
+        self.write_segment_size()
+    }
+
+    /// A run of Voids that can hold `len` bytes. A run right after a SeekHead
+    /// must also leave [`SEEK_HEAD_RESERVE`] bytes so the SeekHead can grow.
+    fn find_void(&self, len: u64) -> Option<usize> {
+        (0..self.elements.len()).find(|i| {
+            let after_seek_head = *i > 0 && self.elements[*i - 1].id == ebml::SEEK_HEAD;
+            let needed = if after_seek_head { len + SEEK_HEAD_RESERVE } else { len };
+            self.elements[*i].id == ebml::VOID && {
+                let available = self.available(*i);
+                (available == needed && !after_seek_head) || available >= needed + 2
+            }
+        })
+    }
+
+    /// Writes `bytes` at the end of the Void run at `index`, so whatever sits
+    /// before the run keeps the leading space. Returns the new offset.
+    fn write_into_void(&mut self, index: usize, id: u32, bytes: &[u8]) -> io::Result<u64> {
+        let available = self.available(index);
+        let len = bytes.len() as u64;
+        if available == len {
+            self.write_in_place(index, id, bytes)?;
+            return Ok(self.elements[index].offset);
+        }
+        let start = self.elements[index].offset;
+        let absorbed = self.elements[index..].iter().take_while(|e| e.id == ebml::VOID).count();
+        let offset = start + available - len;
+        let void = ebml::void_header(available - len).expect("leading Void of two or more bytes");
+
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.write_all(&void)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)?;
+        self.elements.splice(index..index + absorbed, [
+            TopLevel { id: ebml::VOID, offset: start, len: available - len },
+            TopLevel { id, offset, len },
+        ]);
+        Ok(offset)
+    }
+
+    fn append(&mut self, id: u32, bytes: &[u8]) -> io::Result<u64> {
+        let file_len = self.file.metadata()?.len();
+        if self.segment_end != file_len {
+            return Err(invalid("Data after the Matroska segment; can't grow it in place".to_string()));
+        }
+        let offset = self.segment_end;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)?;
+        self.segment_end += bytes.len() as u64;
+        self.elements.push(TopLevel { id, offset, len: bytes.len() as u64 });
+        self.write_segment_size()?;
+        Ok(offset)
+    }
+
+    fn write_segment_size(&mut self) -> io::Result<()> {
+        if self.segment_size_known {
+            let size = self.segment_end - self.segment_data_start;
+            let encoded = ebml::encode_size_width(size, self.segment_size_len)
+                .ok_or_else(|| invalid("Segment size field is too small for the new size".to_string()))?;
+            self.file.seek(SeekFrom::Start(self.segment_size_pos))?;
+            self.file.write_all(&encoded)?;
+        }
+        Ok(())
+    }
+
+    /// Points the SeekHead entry for `id` at `offset`, or drops it. When the
+    /// first SeekHead has no room, the full index moves to a second SeekHead
+    /// and the first keeps a single entry pointing at it. Files without a
+    /// SeekHead are left without one.
+    fn set_seek_entry(&mut self, id: u32, offset: Option<u64>) -> io::Result<()> {
+        let Some(primary) = self.elements.iter().position(|e| e.id == ebml::SEEK_HEAD) else {
+            return Ok(());
+        };
+        let primary_offset = self.elements[primary].offset;
+        let seek_head_id = ebml::encode_id(ebml::SEEK_HEAD);
+
+        let mut entries = seek_entries(&self.read_at(primary)?)?;
+        let secondary_offset = entries.iter().position(|(entry_id, _)| *entry_id == seek_head_id)
+            .map(|i| self.segment_data_start + entries.remove(i).1);
+        if let Some(index) = secondary_offset.and_then(|o| self.index_at(o)) {
+            entries.extend(seek_entries(&self.read_at(index)?)?);
+        }
+
+        let id_bytes = ebml::encode_id(id);
+        entries.retain(|(entry_id, _)| *entry_id != id_bytes);
+        if let Some(offset) = offset {
+            entries.push((id_bytes, offset - self.segment_data_start));
+        }
+        let payload: Vec<u8> = entries.iter().flat_map(|(entry_id, position)| seek_entry(entry_id, *position)).collect();
+        let full = ebml::element(ebml::SEEK_HEAD, &payload);
+
+        if self.available(primary) >= full.len() as u64 && self.write_in_place(primary, ebml::SEEK_HEAD, &full)? {
+            if let Some(index) = secondary_offset.and_then(|o| self.index_at(o)) {
+                self.remove(index)?;
+            }
+            return Ok(());
+        }
+
+        let existing = secondary_offset.and_then(|o| self.index_at(o));
+        let secondary = self.place(existing, ebml::SEEK_HEAD, &full)?;
+        let pointer = ebml::element(ebml::SEEK_HEAD, &seek_entry(&seek_head_id, secondary - self.segment_data_start));
+        let primary = self.index_at(primary_offset).expect("first SeekHead stays in place");
+        if self.available(primary) < pointer.len() as u64 || !self.write_in_place(primary, ebml::SEEK_HEAD, &pointer)? {
+            return Err(invalid("No room left in the SeekHead for the relocated element".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn seek_entries(data: &[u8]) -> io::Result<Vec<(Vec<u8>, u64)>> {
+    let mut entries = Vec::new();
+    for seek in ebml::children(data)?.iter().filter(|c| c.id == ebml::SEEK) {
+        let fields = ebml::children(seek.data)?;
+        let id = fields.iter().find(|f| f.id == ebml::SEEK_ID).map(|f| f.data.to_vec());
+        let position = fields.iter().find(|f| f.id == ebml::SEEK_POSITION).map(|f| ebml::read_uint(f.data));
+        if let (Some(id), Some(position)) = (id, position) {
+            entries.push((id, position));
+        }
+    }
+    Ok(entries)
+}
+
+fn seek_entry(id_bytes: &[u8], position: u64) -> Vec<u8> {
+    let mut entry = ebml::element(ebml::SEEK_ID, id_bytes);
+    entry.extend(ebml::uint_element(ebml::SEEK_POSITION, position));
+    ebml::element(ebml::SEEK, &entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    fn void(len: u64) -> Vec<u8> {
+        let mut out = ebml::void_header(len).unwrap();
+        out.resize(len as usize, 0);
+        out
+    }
+
+    fn tags(len: usize) -> Vec<u8> {
+        vec![b't'; len]
+    }
+
+    fn cluster() -> Vec<u8> {
+        ebml::element(ebml::CLUSTER, &[0x42; 32])
+    }
+
+    fn seek_head(entries: &[(u32, u64)]) -> Vec<u8> {
+        let payload: Vec<u8> = entries.iter().flat_map(|(id, position)| seek_entry(&ebml::encode_id(*id), *position)).collect();
+        ebml::element(ebml::SEEK_HEAD, &payload)
+    }
+
+    /// Writes a file whose segment holds a SeekHead indexing `indexed`, then
+    /// `reserve` bytes of Void when non-zero, then `elements`.
+    fn indexed_file(indexed: &[u32], reserve: u64, elements: &[Vec<u8>]) -> NamedTempFile {
+        let draft = seek_head(&indexed.iter().map(|id| (*id, 0x80)).collect::<Vec<_>>());
+        let mut position = draft.len() as u64 + reserve;
+        let mut entries = Vec::new();
+        for element in elements {
+            let id = ebml::read_header(&mut element.as_slice()).unwrap().id;
+            if indexed.contains(&id) {
+                assert!(position < 0x100, "fixture positions stay one byte wide");
+                entries.push((id, position));
+            }
+            position += element.len() as u64;
+        }
+        let mut children = vec![seek_head(&entries)];
+        if reserve > 0 {
+            children.push(void(reserve));
+        }
+        children.extend_from_slice(elements);
+        mkv(&children, 8)
+    }
+
+    fn mkv(children: &[Vec<u8>], size_width: usize) -> NamedTempFile {
+        let body = children.concat();
+        let mut bytes = ebml::element(ebml::EBML_HEADER, &ebml::string_element(ebml::DOC_TYPE, "matroska"));
+        bytes.extend(ebml::encode_id(ebml::SEGMENT));
+        bytes.extend(ebml::encode_size_width(body.len() as u64, size_width).unwrap());
+        bytes.extend(body);
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), bytes).unwrap();
+        file
+    }
+
+    /// Reopens the file, so the segment size and element chain are checked
+    /// the same way a fresh read would, and lists its top-level elements.
+    fn layout(path: &Path) -> (MatroskaFile, Vec<(u32, u64)>) {
+        let file = MatroskaFile::edit(path).unwrap();
+        assert_eq!(file.segment_end, fs::metadata(path).unwrap().len(), "segment size covers the file");
+        let layout = file.elements.iter().map(|e| (e.id, e.len)).collect();
+        (file, layout)
+    }
+
+    fn offset_of(file: &MatroskaFile, id: u32) -> u64 {
+        file.elements.iter().find(|e| e.id == id).unwrap().offset
+    }
+
+    /// Every SeekHead entry as (id, absolute offset), following a pointer to
+    /// a second SeekHead.
+    fn index(file: &mut MatroskaFile) -> Vec<(u32, u64)> {
+        let mut entries = seek_entries(&file.read_element(ebml::SEEK_HEAD).unwrap().unwrap()).unwrap();
+        let pointer = entries.iter().position(|(id, _)| ebml::read_uint(id) == ebml::SEEK_HEAD as u64);
+        if let Some(pointer) = pointer {
+            let offset = file.segment_data_start + entries.remove(pointer).1;
+            let secondary = file.index_at(offset).expect("pointer lands on an element");
+            assert_eq!(file.elements[secondary].id, ebml::SEEK_HEAD);
+            entries.extend(seek_entries(&file.read_at(secondary).unwrap()).unwrap());
+        }
+        let entries: Vec<(u32, u64)> = entries.into_iter()
+            .map(|(id, position)| (ebml::read_uint(&id) as u32, file.segment_data_start + position))
+            .collect();
+        for (id, offset) in &entries {
+            let element = file.index_at(*offset).expect("entry lands on an element");
+            assert_eq!(file.elements[element].id, *id);
+        }
+        entries
+    }
+
+    #[test]
+    fn replaces_in_place_with_void_padding() {
+        let fixture = indexed_file(&[ebml::TAGS], 0, &[ebml::element(ebml::TAGS, &tags(40)), cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        let (offset, cluster_offset) = (offset_of(&file, ebml::TAGS), offset_of(&file, ebml::CLUSTER));
+        file.replace_element(ebml::TAGS, Some(&tags(30))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(&elements[1..], [(ebml::TAGS, 35), (ebml::VOID, 10), (ebml::CLUSTER, 37)]);
+        assert_eq!((offset_of(&file, ebml::TAGS), offset_of(&file, ebml::CLUSTER)), (offset, cluster_offset));
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(30)));
+        assert_eq!(index(&mut file), [(ebml::TAGS, offset)]);
+    }
+
+    #[test]
+    fn exact_fit_absorbs_following_void() {
+        let fixture = indexed_file(&[ebml::TAGS], 0, &[ebml::element(ebml::TAGS, &tags(20)), void(10), cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        file.replace_element(ebml::TAGS, Some(&tags(30))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(&elements[1..], [(ebml::TAGS, 35), (ebml::CLUSTER, 37)]);
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(30)));
+    }
+
+    #[test]
+    fn one_byte_of_slack_is_not_written_in_place() {
+        let fixture = indexed_file(&[ebml::TAGS], 0, &[ebml::element(ebml::TAGS, &tags(10)), cluster()]);
+        let before = fs::read(fixture.path()).unwrap();
+        let (mut file, _) = layout(fixture.path());
+        assert!(!file.replace_before_clusters(ebml::TAGS, &tags(9)).unwrap());
+        assert_eq!(fs::read(fixture.path()).unwrap(), before, "file untouched when there is no room");
+
+        file.replace_element(ebml::TAGS, Some(&tags(9))).unwrap();
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(&elements[1..], [(ebml::VOID, 15), (ebml::CLUSTER, 37), (ebml::TAGS, 14)]);
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(9)));
+        let appended = offset_of(&file, ebml::TAGS);
+        assert_eq!(index(&mut file), [(ebml::TAGS, appended)]);
+    }
+
+    #[test]
+    fn reuses_void_after_seek_head_but_keeps_reserve() {
+        let fixture = indexed_file(&[ebml::INFO], 120, &[ebml::element(ebml::INFO, &[0; 8]), cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        let void_offset = offset_of(&file, ebml::VOID);
+        file.replace_element(ebml::TAGS, Some(&tags(40))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        let tags_offset = offset_of(&file, ebml::TAGS);
+        assert_eq!(tags_offset, void_offset + 120 - 45, "written at the end of the Void");
+        assert_eq!(elements.iter().filter(|(id, _)| *id == ebml::TAGS).count(), 1);
+        assert!(index(&mut file).contains(&(ebml::TAGS, tags_offset)));
+
+        // Too big to leave the SeekHead its reserve, so it goes to the end.
+        file.replace_element(ebml::ATTACHMENTS, Some(&[0; 30])).unwrap();
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(elements.last(), Some(&(ebml::ATTACHMENTS, 35)));
+        let attachments = offset_of(&file, ebml::ATTACHMENTS);
+        assert!(index(&mut file).contains(&(ebml::ATTACHMENTS, attachments)));
+    }
+
+    #[test]
+    fn reuses_void_elsewhere_in_the_segment() {
+        let info = ebml::element(ebml::INFO, &[0; 8]);
+        let fixture = indexed_file(&[ebml::INFO], 20, &[info, void(60), cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        let void_offset = file.elements[3].offset;
+        file.replace_element(ebml::TAGS, Some(&tags(20))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(&elements[3..], [(ebml::VOID, 35), (ebml::TAGS, 25), (ebml::CLUSTER, 37)]);
+        assert_eq!(offset_of(&file, ebml::TAGS), void_offset + 35);
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(20)));
+    }
+
+    #[test]
+    fn appends_and_updates_seek_head() {
+        let fixture = indexed_file(&[ebml::TAGS], 64, &[ebml::element(ebml::TAGS, &tags(10)), cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        let old_offset = offset_of(&file, ebml::TAGS);
+        file.replace_element(ebml::TAGS, Some(&tags(200))).unwrap();
+
+        // The old copy is voided and merged into the Void behind the SeekHead.
+        let (mut file, elements) = layout(fixture.path());
+        let ids: Vec<u32> = elements.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [ebml::SEEK_HEAD, ebml::VOID, ebml::CLUSTER, ebml::TAGS]);
+        assert_eq!(elements.last(), Some(&(ebml::TAGS, 206)));
+        let new_offset = offset_of(&file, ebml::TAGS);
+        assert!(new_offset > old_offset);
+        assert_eq!(index(&mut file), [(ebml::TAGS, new_offset)]);
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(200)));
+    }
+
+    #[test]
+    fn full_seek_head_moves_index_to_a_second_one() {
+        let info = ebml::element(ebml::INFO, &[0; 8]);
+        let fixture = indexed_file(&[ebml::INFO, ebml::CLUSTER], 0, &[info, cluster()]);
+        let (mut file, _) = layout(fixture.path());
+        let (info_offset, cluster_offset) = (offset_of(&file, ebml::INFO), offset_of(&file, ebml::CLUSTER));
+        file.replace_element(ebml::TAGS, Some(&tags(10))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        let tags_offset = offset_of(&file, ebml::TAGS);
+        assert_eq!(elements.iter().filter(|(id, _)| *id == ebml::SEEK_HEAD).count(), 2);
+        let primary = seek_entries(&file.read_element(ebml::SEEK_HEAD).unwrap().unwrap()).unwrap();
+        assert_eq!(primary.len(), 1, "first SeekHead only points at the second");
+        let mut entries = index(&mut file);
+        entries.sort();
+        let mut expected = vec![(ebml::INFO, info_offset), (ebml::CLUSTER, cluster_offset), (ebml::TAGS, tags_offset)];
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        // Later edits go through the second SeekHead.
+        file.replace_element(ebml::TAGS, None).unwrap();
+        let (mut file, _) = layout(fixture.path());
+        let mut entries = index(&mut file);
+        entries.sort();
+        expected.retain(|(id, _)| *id != ebml::TAGS);
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn tail_element_is_truncated_and_rewritten() {
+        let fixture = indexed_file(&[ebml::TAGS], 0, &[cluster(), ebml::element(ebml::TAGS, &tags(10))]);
+        let (mut file, _) = layout(fixture.path());
+        let offset = offset_of(&file, ebml::TAGS);
+        file.replace_element(ebml::TAGS, Some(&tags(100))).unwrap();
+
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(elements.last(), Some(&(ebml::TAGS, 105)));
+        assert_eq!(offset_of(&file, ebml::TAGS), offset);
+        assert_eq!(fs::metadata(fixture.path()).unwrap().len(), offset + 105);
+
+        file.replace_element(ebml::TAGS, None).unwrap();
+        let (mut file, elements) = layout(fixture.path());
+        assert_eq!(elements.last(), Some(&(ebml::CLUSTER, 37)));
+        assert_eq!(fs::metadata(fixture.path()).unwrap().len(), offset);
+        assert_eq!(index(&mut file), []);
+    }
+
+    #[test]
+    fn segment_size_must_fit_its_field() {
+        let fixture = mkv(&[cluster()], 1);
+        let (mut file, _) = layout(fixture.path());
+        file.replace_element(ebml::TAGS, Some(&tags(80))).unwrap();
+        assert_eq!(layout(fixture.path()).1, [(ebml::CLUSTER, 37), (ebml::TAGS, 85)]);
+
+        let error = file.replace_element(ebml::ATTACHMENTS, Some(&[0; 10])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_read_only_files() {
+        let fixture = indexed_file(&[ebml::TAGS], 0, &[ebml::element(ebml::TAGS, &tags(10)), cluster()]);
+        let mut permissions = fs::metadata(fixture.path()).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(fixture.path(), permissions).unwrap();
+        let mut file = MatroskaFile::open(fixture.path()).unwrap();
+        assert_eq!(file.read_element(ebml::TAGS).unwrap(), Some(tags(10)));
+    }
+}
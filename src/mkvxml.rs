@@ -1,189 +1,409 @@
-use std::fs::File;
-use std::io::Write;
-use xml::reader::{EventReader, XmlEvent};
-use xml::writer::{EmitterConfig, EventWriter};
-
-pub fn convert_to_mkv_tags(input_xml_path: &str, output_xml_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::open(input_xml_path)?;
-    let parser = EventReader::new(file);
-
-    let output_file = File::create(output_xml_path)?;
-    let config = EmitterConfig::new().perform_indent(true); // For pretty printing (optional)
-    let mut writer = EventWriter::new_with_config(output_file, config);
-
-    writer.write(xml::writer::XmlEvent::StartDocument {
-        version: xml::common::XmlVersion::Version10,
-        encoding: Some("UTF-8"),
-        standalone: None,
-    })?;
-
-    writer.write(xml::writer::XmlEvent::start_element("Tags"))?;
-    writer.write(xml::writer::XmlEvent::start_element("Tag"))?;
-
-    let mut current_tag_name: Option<String> = None;
-    let mut is_tv_episode = false;
-    let mut imdb_id: Option<String> = None;
-    let mut in_uniqueid = false;
-    let mut uniqueid_type: Option<String> = None;
-
-    let mut directors = Vec::new();
-    let mut writers = Vec::new();
-    let mut actors = Vec::new();
-    let mut collection_name = None;
-    let mut collection_overview = None;
-    let mut plot = None;
-    let mut outline = None;
-
-    let mut inside_actor = false;
-    let mut inside_name = false;
-    let mut current_actor_name = String::new();
-
-    for e in parser {
-        match e? {
-            XmlEvent::StartElement { name, attributes, .. } => {
-                current_tag_name = Some(name.local_name.clone());
-                match name.local_name.as_str() {
-                    "actor" => inside_actor = true,
-                    "name" if inside_actor => inside_name = true,
-                    "episodedetails" => is_tv_episode = true,
-                    "movie" => is_tv_episode = false,
-                    "uniqueid" => {
-                        in_uniqueid = true;
-                        for attr in attributes {
-                            if attr.name.local_name == "type" {
-                                uniqueid_type = Some(attr.value);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            XmlEvent::Characters(text) => {
-                match current_tag_name.as_ref().map(|s| s.as_str()) {
-                    Some("name") if inside_actor && inside_name => {
-                        current_actor_name = text.to_string();
-                    }
-                    Some("director") => {
-                        directors.push(text.to_string());
-                    }
-                    Some("credits") => {
-                        writers.push(text.to_string());
-                    }
-                    Some("plot") => plot = Some(text.to_string()),
-                    Some("outline") => outline = Some(text.to_string()),
-                    Some("overview") => collection_overview = Some(text.to_string()),
-                    Some("name") if current_tag_name.as_ref().map(|s| s.as_str()) == Some("set") => {
-                        collection_name = Some(text.to_string())
-                    }
-                    Some("genre") => write_simple_tag(&mut writer, "GENRE", &text)?,
-                    Some("id") if !is_tv_episode => {
-                        write_simple_tag(&mut writer, "IMDB", &text)?
-                    }
-                    Some("uniqueid") if in_uniqueid => {
-                        if let Some(ref id_type) = uniqueid_type {
-                            if id_type == "imdb" {
-                                imdb_id = Some(text.to_string());
-                            }
-                        }
-                    }
-                    Some("title") => write_simple_tag(&mut writer, "TITLE", &text)?,
-                    Some("showtitle") => write_simple_tag(&mut writer, "SUBTITLE", &text)?,
-                    Some("season") => write_simple_tag(&mut writer, "SEASON", &text)?,
-                    Some("episode") => write_simple_tag(&mut writer, "EPISODE", &text)?,
-                    Some("originaltitle") => write_simple_tag(&mut writer, "ORIGINALTITLE", &text)?,
-                    Some("year") => write_simple_tag(&mut writer, "YEAR", &text)?,
-                    Some("tagline") => write_simple_tag(&mut writer, "TAGLINE", &text)?,
-                    Some("runtime") => write_simple_tag(&mut writer, "RUNTIME", &text)?,
-                    Some("mpaa") => write_simple_tag(&mut writer, "MPAA", &text)?,
-                    Some("certification") => write_simple_tag(&mut writer, "CERTIFICATION", &text)?,
-                    Some("tmdbid") => write_simple_tag(&mut writer, "TMDB", &text)?,
-                    Some("country") => write_simple_tag(&mut writer, "COUNTRY", &text)?,
-                    Some("premiered") => write_simple_tag(&mut writer, "PREMIERED", &text)?,
-                    Some("studio") => write_simple_tag(&mut writer, "STUDIO", &text)?,
-                    _ => {}
-                }
-            }
-            XmlEvent::EndElement { name } => {
-                match name.local_name.as_str() {
-                    "actor" => {
-                        if !current_actor_name.is_empty() {
-                            actors.push(current_actor_name.clone());
-                            current_actor_name.clear();
-                        }
-                        inside_actor = false;
-                    }
-                    "name" if inside_actor => inside_name = false,
-                    "episodedetails" | "movie" => {
-                        if let Some(ref imdb) = imdb_id {
-                            write_simple_tag(&mut writer, "IMDB", imdb)?;
-                        }
-                        write_collected_tags(&mut writer, &[
-                            ("DESCRIPTION", plot.as_ref()),
-                            ("SUMMARY", outline.as_ref()),
-                            ("Collection Name", collection_name.as_ref()),
-                            ("Collection Overview", collection_overview.as_ref()),
-                        ])?;
-
-                        write_list_tags(&mut writer, &[
-                            ("Director", &directors),
-                            ("WRITER", &writers),
-                            ("Actor", &actors),
-                        ])?;
-                    }
-                    "uniqueid" => {
-                        in_uniqueid = false;
-                        uniqueid_type = None;
-                    }
-                    _ => {}
-                }
+use crate::mapping::MappingTable;
+use crate::nfo::{MovieSet, Nfo, NfoKind, Person, UniqueId};
+use crate::tags::TagSet;
+
+/// Target levels from the Matroska tagging spec, each with the TargetType
+/// names it goes by. The first name is the one used in tag names.
+const LEVELS: &[(u64, &[&str])] = &[
+    (70, &["COLLECTION"]),
+    (60, &["SEASON", "EDITION", "ISSUE", "VOLUME", "OPUS", "SEQUEL"]),
+    (50, &["EPISODE", "MOVIE", "ALBUM", "OPERA", "CONCERT"]),
+    (40, &["PART", "SESSION"]),
+    (30, &["CHAPTER", "TRACK", "SONG"]),
+    (20, &["SCENE", "SUBTRACK", "MOVEMENT"]),
+    (10, &["SHOT"]),
+];
+
+/// The level a Tag applies to when its Targets don't say.
+pub const DEFAULT_LEVEL: u64 = 50;
+
+/// Which Tag a tag name belongs in: its target level, and which of the Tags
+/// at that level, counting from 1, when there are several as for the
+/// episodes of a multi-episode file.
+pub type Target = (u64, usize);
+
+/// Splits a tag name like `COLLECTION/TITLE` or `70/TITLE` into its target
+/// and SimpleTag name. Names without a level prefix are at 50, the episode
+/// or movie itself. A copy number follows the level, as in `EPISODE#2/TITLE`.
+pub fn split_target(name: &str) -> (Target, &str) {
+    if let Some((prefix, rest)) = name.split_once('/') {
+        let (prefix, copy) = match prefix.split_once('#') {
+            Some((prefix, copy)) => (prefix, copy.parse::<usize>().ok().filter(|c| *c > 0)),
+            None => (prefix, Some(1)),
+        };
+        let level = prefix.parse::<u64>().ok().or_else(|| LEVELS.iter()
+            .find(|(_, names)| names.iter().any(|n| n.eq_ignore_ascii_case(prefix)))
+            .map(|(level, _)| *level));
+        if let (Some(level), Some(copy)) = (level, copy) {
+            return ((level, copy), rest);
+        }
+    }
+    ((DEFAULT_LEVEL, 1), name)
+}
+
+/// The tag name for `name` in `target`, the inverse of [`split_target`].
+pub fn target_name((level, copy): Target, name: &str) -> String {
+    let copy = if copy > 1 { format!("#{}", copy) } else { String::new() };
+    if level == DEFAULT_LEVEL && copy.is_empty() {
+        return name.to_string();
+    }
+    match LEVELS.iter().find(|(l, _)| *l == level) {
+        Some((_, names)) => format!("{}{}/{}", names[0], copy, name),
+        None => format!("{}{}/{}", level, copy, name),
+    }
+}
+
+/// The TargetType written next to a level. Level 50 is an episode or a
+/// movie, as [`has_episodes`] decides.
+pub fn target_type(level: u64, episode: bool) -> Option<&'static str> {
+    match level {
+        DEFAULT_LEVEL if !episode => Some("MOVIE"),
+        _ => LEVELS.iter().find(|(l, _)| *l == level).map(|(_, names)| names[0]),
+    }
+}
+
+/// Groups tags by target, highest level first, with the target prefix taken
+/// off the names.
+pub fn group_by_target(tags: &TagSet) -> Vec<(Target, TagSet)> {
+    let mut groups: Vec<(Target, TagSet)> = Vec::new();
+    for tag in tags.iter() {
+        let (target, name) = split_target(&tag.name);
+        match groups.iter_mut().find(|(t, _)| *t == target) {
+            Some((_, group)) => group.push(name, &tag.value),
+            None => {
+                let mut group = TagSet::new();
+                group.push(name, &tag.value);
+                groups.push((target, group));
             }
-            _ => {}
         }
     }
+    groups.sort_by_key(|((level, copy), _)| (std::cmp::Reverse(*level), *copy));
+    groups
+}
+
+/// Whether the level 50 Tags in `tags` are episodes: the NFO they read back
+/// as is a TV episode, or there are several of them.
+pub fn has_episodes(tags: &TagSet) -> bool {
+    tags_to_nfo(tags).is_tv_episode()
+        || tags.iter().any(|tag| matches!(split_target(&tag.name), ((DEFAULT_LEVEL, copy), _) if copy > 1))
+}
+
+/// A SimpleTag and the SimpleTags nested in it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleTag {
+    pub name: String,
+    pub value: String,
+    pub children: Vec<SimpleTag>,
+}
 
-    writer.write(xml::writer::XmlEvent::end_element())?; // </Tag>
-    writer.write(xml::writer::XmlEvent::end_element())?; // </Tags>
+/// Nests each `PARENT.CHILD` tag in the closest `PARENT` before it. When
+/// there is none, as for `ORIGINAL.TITLE`, an empty `PARENT` is added to
+/// hold it.
+pub fn nest(tags: &TagSet) -> Vec<SimpleTag> {
+    nest_entries(tags.iter().map(|tag| (tag.name.clone(), tag.value.clone())).collect())
+}
 
-    Ok(())
+fn nest_entries(entries: Vec<(String, String)>) -> Vec<SimpleTag> {
+    let mut nested: Vec<(SimpleTag, Vec<(String, String)>)> = Vec::new();
+    for (name, value) in entries {
+        let Some((parent, child)) = name.split_once('.') else {
+            nested.push((SimpleTag { name, value, children: Vec::new() }, Vec::new()));
+            continue;
+        };
+        let index = match nested.iter().rposition(|(t, _)| t.name == parent) {
+            Some(index) => index,
+            None => {
+                nested.push((SimpleTag { name: parent.to_string(), ..SimpleTag::default() }, Vec::new()));
+                nested.len() - 1
+            }
+        };
+        nested[index].1.push((child.to_string(), value));
+    }
+    nested.into_iter().map(|(mut tag, children)| {
+        tag.children = nest_entries(children);
+        tag
+    }).collect()
 }
 
-fn write_simple_tag<W: Write>(writer: &mut EventWriter<W>, name: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
-    writer.write(xml::writer::XmlEvent::start_element("Simple"))?;
-    
-    writer.write(xml::writer::XmlEvent::start_element("Name"))?;
-    writer.write(xml::writer::XmlEvent::characters(name))?;
-    writer.write(xml::writer::XmlEvent::end_element())?;
-    
-    writer.write(xml::writer::XmlEvent::start_element("String"))?;
-    writer.write(xml::writer::XmlEvent::characters(value))?;
-    writer.write(xml::writer::XmlEvent::end_element())?;
-    
-    writer.write(xml::writer::XmlEvent::end_element())?;
-    Ok(())
+/// Maps an NFO onto the Matroska SimpleTag names written by nfo2tags, using
+/// the built-in table.
+pub fn nfo_to_tags(nfo: &Nfo) -> TagSet {
+    map_tags(&MappingTable::mkv(), nfo)
 }
 
-fn write_collected_tags<W: Write>(
-    writer: &mut EventWriter<W>, 
-    tags: &[(&str, Option<&String>)]
-) -> Result<(), Box<dyn std::error::Error>> {
-    for (name, value) in tags {
-        if let Some(v) = value {
-            write_simple_tag(writer, name, v)?;
+/// Maps an NFO with `table`, normalizing the level names. Each further
+/// episode of a multi-episode file gets a level 50 Tag of its own; the show
+/// and season are shared.
+pub fn map_tags(table: &MappingTable, nfo: &Nfo) -> TagSet {
+    let mut tags = TagSet::new();
+    for tag in table.apply(nfo).iter() {
+        let (target, name) = split_target(&tag.name);
+        tags.push(&target_name(target, name), &tag.value);
+    }
+    for (copy, episode) in nfo.more_episodes.iter().enumerate() {
+        for tag in table.apply(episode).iter() {
+            if let ((DEFAULT_LEVEL, 1), name) = split_target(&tag.name) {
+                tags.push(&target_name((DEFAULT_LEVEL, copy + 2), name), &tag.value);
+            }
         }
     }
-    Ok(())
+    tags
 }
 
-fn write_list_tags<W: Write>(
-    writer: &mut EventWriter<W>,
-    tags: &[(&str, &Vec<String>)]
-) -> Result<(), Box<dyn std::error::Error>> {
-    for (name, values) in tags {
-        if !values.is_empty() {
-            write_simple_tag(writer, name, &values.join(","))?;
+/// Rebuilds an NFO from tags written by [`nfo_to_tags`], or with the legacy
+/// names older versions wrote, all at one level. `EPISODE#2/` tags and on
+/// become [`Nfo::more_episodes`].
+pub fn tags_to_nfo(tags: &TagSet) -> Nfo {
+    let first = |names: &[&str]| names.iter().find_map(|name| tags.get(name));
+    let text = |names: &[&str]| first(names).unwrap_or_default().to_string();
+    let number = |names: &[&str]| first(names).and_then(|v| v.trim().parse().ok());
+    let all = |name: &str| tags.get_all(name).into_iter().map(String::from).collect::<Vec<_>>();
+    // Spec names repeat a tag per value; the legacy ones joined them.
+    let list = |name: &str, legacy: &str| match all(name) {
+        values if values.is_empty() => tags.get(legacy).map(split_list).unwrap_or_default(),
+        values => values,
+    };
+    let season = number(&["SEASON/PART_NUMBER", "SEASON"]);
+    let episode = number(&["PART_NUMBER", "EPISODE"]);
+    let collection = tags.get("COLLECTION/TITLE");
+    let is_episode = season.is_some() || episode.is_some() || tags.get("SUBTITLE").is_some()
+        || tags.get("CONTENT_TYPE") == Some("Episode");
+    let released = text(&["DATE_RELEASED", "PREMIERED"]);
+
+    let mut nfo = Nfo {
+        title: text(&["TITLE"]),
+        showtitle: match collection {
+            Some(show) if is_episode => show.to_string(),
+            _ => text(&["SUBTITLE"]),
+        },
+        season,
+        episode,
+        originaltitle: text(&["ORIGINAL.TITLE", "ORIGINALTITLE"]),
+        sorttitle: text(&["TITLE.SORT_WITH"]),
+        year: number(&["YEAR"]).or_else(|| released.get(..4).and_then(|y| y.parse().ok())),
+        tagline: text(&["TAGLINE"]),
+        runtime: number(&["RUNTIME"]),
+        mpaa: text(&["LAW_RATING", "MPAA"]),
+        certification: text(&["CERTIFICATION"]),
+        countries: all("COUNTRY"),
+        premiered: released,
+        studios: list("PRODUCTION_STUDIO", "STUDIO"),
+        genres: all("GENRE"),
+        tags: tags.get("KEYWORDS").map(split_list).unwrap_or_default(),
+        plot: text(&["SYNOPSIS", "DESCRIPTION"]),
+        outline: text(&["SUMMARY"]),
+        directors: list("DIRECTOR", "Director"),
+        credits: list("WRITTEN_BY", "WRITER"),
+        ..Nfo::default()
+    };
+    for tag in tags.iter() {
+        match tag.name.as_str() {
+            "ACTOR" => nfo.actors.push(Person { name: tag.value.clone(), ..Person::default() }),
+            "ACTOR.CHARACTER" => if let Some(actor) = nfo.actors.last_mut() {
+                actor.role = tag.value.clone();
+            },
+            _ => {}
+        }
+    }
+    if nfo.actors.is_empty() {
+        nfo.actors = tags.get("Actor").map(split_list).unwrap_or_default().into_iter()
+            .map(|name| Person { name, ..Person::default() })
+            .collect();
+    }
+    for (order, actor) in nfo.actors.iter_mut().enumerate() {
+        actor.order = Some(order as u32);
+    }
+    nfo.producers = all("PRODUCER").into_iter()
+        .map(|name| Person { name, ..Person::default() })
+        .collect();
+    if let Some(name) = collection.filter(|_| !is_episode).or_else(|| tags.get("Collection Name")) {
+        let overview = first(&["COLLECTION/DESCRIPTION", "Collection Overview"]);
+        nfo.set = Some(MovieSet::new(name, overview.unwrap_or_default()));
+    }
+    let ids = [
+        ("imdb", tags.get("IMDB")),
+        ("tmdb", tags.get("TMDB").map(|id| id.rsplit('/').next().unwrap_or(id))),
+        ("tvdb", tags.get("TVDB2").map(|id| id.rsplit('/').next().unwrap_or(id))),
+    ];
+    for (id_type, value) in ids {
+        if let Some(value) = value {
+            let default = nfo.unique_ids.is_empty();
+            nfo.unique_ids.push(UniqueId { id_type: id_type.to_string(), default, value: value.to_string() });
+        }
+    }
+    if nfo.is_tv_episode() {
+        nfo.kind = NfoKind::Episode;
+        nfo.aired = nfo.premiered.clone();
+    }
+    for ((level, copy), group) in group_by_target(tags) {
+        if level == DEFAULT_LEVEL && copy > 1 {
+            let episode = tags_to_nfo(&group);
+            nfo.more_episodes.push(Nfo {
+                kind: NfoKind::Episode,
+                showtitle: nfo.showtitle.clone(),
+                season: nfo.season,
+                aired: episode.premiered.clone(),
+                ..episode
+            });
         }
     }
-    Ok(())
+    nfo
+}
+
+/// Splits the comma-joined legacy Director/WRITER/Actor values.
+pub(crate) fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(String::from).collect()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mkv_tags(xml: &str) -> TagSet {
+        map_tags(&MappingTable::mkv(), &Nfo::from_xml(xml).unwrap())
+    }
+
+    #[test]
+    fn episode_without_a_season_is_an_episode() {
+        let tags = mkv_tags("<episodedetails><title>Pilot</title><showtitle>Lost</showtitle></episodedetails>");
+        assert!(has_episodes(&tags));
+        assert_eq!(target_type(DEFAULT_LEVEL, has_episodes(&tags)), Some("EPISODE"));
+    }
+
+    #[test]
+    fn movie_in_a_set_is_a_movie() {
+        let tags = mkv_tags("<movie><title>Heat</title><set><name>Mann</name></set></movie>");
+        assert!(!has_episodes(&tags));
+        assert_eq!(target_type(DEFAULT_LEVEL, has_episodes(&tags)), Some("MOVIE"));
+        assert_eq!(target_type(70, false), Some("COLLECTION"));
+    }
+
+    const TWO_EPISODES: &str = "<episodedetails>
+        <title>Pilot (1)</title><showtitle>Lost</showtitle><season>1</season><episode>1</episode>
+        <plot>The survivors.</plot><aired>2004-09-22</aired>
+    </episodedetails>
+    <episodedetails>
+        <title>Pilot (2)</title><showtitle>Lost</showtitle><season>1</season><episode>2</episode>
+        <plot>The transmission.</plot><aired>2004-09-29</aired>
+    </episodedetails>";
+
+    #[test]
+    fn multi_episode_nfo_gets_a_level_50_tag_per_episode() {
+        let nfo = Nfo::from_xml(TWO_EPISODES).unwrap();
+        assert_eq!(nfo.more_episodes.len(), 1);
+        assert_eq!(nfo.more_episodes[0].title, "Pilot (2)");
+        assert_eq!(nfo.more_episodes[0].kind, NfoKind::Episode);
+
+        let tags = map_tags(&MappingTable::mkv(), &nfo);
+        assert_eq!(tags.get("TITLE"), Some("Pilot (1)"));
+        assert_eq!(tags.get("PART_NUMBER"), Some("1"));
+        assert_eq!(tags.get("EPISODE#2/TITLE"), Some("Pilot (2)"));
+        assert_eq!(tags.get("EPISODE#2/PART_NUMBER"), Some("2"));
+        assert_eq!(tags.get("EPISODE#2/SYNOPSIS"), Some("The transmission."));
+        // The show and season are shared, not copied.
+        assert_eq!(tags.get_all("COLLECTION/TITLE"), ["Lost"]);
+        assert!(tags.get("EPISODE#3/TITLE").is_none());
+
+        let groups = group_by_target(&tags);
+        let targets: Vec<Target> = groups.iter().map(|(target, _)| *target).collect();
+        assert_eq!(targets, [(70, 1), (60, 1), (DEFAULT_LEVEL, 1), (DEFAULT_LEVEL, 2)]);
+    }
+
+    #[test]
+    fn multi_episode_tags_read_back_as_more_episodes() {
+        let tags = map_tags(&MappingTable::mkv(), &Nfo::from_xml(TWO_EPISODES).unwrap());
+        let nfo = tags_to_nfo(&tags);
+        assert_eq!(nfo.kind, NfoKind::Episode);
+        assert_eq!((nfo.title.as_str(), nfo.season, nfo.episode), ("Pilot (1)", Some(1), Some(1)));
+        assert_eq!(nfo.showtitle, "Lost");
+        assert_eq!(nfo.more_episodes.len(), 1);
+        let second = &nfo.more_episodes[0];
+        assert_eq!(second.kind, NfoKind::Episode);
+        assert_eq!((second.title.as_str(), second.season, second.episode), ("Pilot (2)", Some(1), Some(2)));
+        assert_eq!(second.showtitle, "Lost");
+        assert_eq!(second.plot, "The transmission.");
+        assert_eq!(second.aired, "2004-09-29");
+    }
+
+    const MOVIE: &str = r#"<movie>
+        <title>Heat</title><originaltitle>Heat (1995)</originaltitle><sorttitle>Heat 1</sorttitle>
+        <plot>A group of professional bank robbers.</plot><outline>Cops and robbers.</outline>
+        <tagline>A Los Angeles crime saga</tagline><runtime>170</runtime><mpaa>R</mpaa>
+        <uniqueid type="imdb" default="true">tt0113277</uniqueid><uniqueid type="tmdb">949</uniqueid>
+        <genre>Crime</genre><genre>Drama</genre><tag>heist</tag><tag>los angeles</tag>
+        <set><name>Mann Collection</name><overview>Films by Michael Mann.</overview></set>
+        <director>Michael Mann</director><credits>Michael Mann</credits>
+        <premiered>1995-12-15</premiered><year>1995</year><studio>Warner Bros.</studio>
+        <actor><name>Val Kilmer</name><role>Chris</role><order>2</order></actor>
+        <actor><name>Al Pacino</name><role>Vincent Hanna</role><order>0</order></actor>
+        <actor><name>Robert De Niro</name><role>Neil McCauley</role><order>1</order></actor>
+    </movie>"#;
+
+    fn actors(nfo: &Nfo) -> Vec<(&str, &str)> {
+        nfo.actors_in_order().into_iter().map(|a| (a.name.as_str(), a.role.as_str())).collect()
+    }
+
+    #[test]
+    fn movie_round_trips_through_spec_tags() {
+        let nfo = Nfo::from_xml(MOVIE).unwrap();
+        let back = tags_to_nfo(&map_tags(&MappingTable::mkv(), &nfo));
+        assert_eq!(back.kind, NfoKind::Movie);
+        assert_eq!(back.title, nfo.title);
+        assert_eq!(back.originaltitle, nfo.originaltitle);
+        assert_eq!(back.sorttitle, nfo.sorttitle);
+        assert_eq!(back.plot, nfo.plot);
+        assert_eq!(back.outline, nfo.outline);
+        assert_eq!(back.mpaa, nfo.mpaa);
+        assert_eq!(back.genres, nfo.genres);
+        assert_eq!(back.tags, nfo.tags);
+        assert_eq!(back.directors, nfo.directors);
+        assert_eq!(back.credits, nfo.credits);
+        assert_eq!(back.studios, nfo.studios);
+        assert_eq!((back.premiered.as_str(), back.year), ("1995-12-15", Some(1995)));
+        let set = back.collection().unwrap();
+        assert_eq!((set.name(), set.overview.as_str()), ("Mann Collection", "Films by Michael Mann."));
+        assert_eq!(actors(&back), actors(&nfo));
+        assert_eq!(back.imdb_id(), Some("tt0113277"));
+        assert_eq!(back.tmdb_id(), Some("949"));
+        assert_eq!(back.default_unique_id().unwrap().id_type, "imdb");
+    }
+
+    #[test]
+    fn episode_round_trips_through_spec_tags() {
+        let mut nfo = Nfo::from_xml("<episodedetails>
+            <title>Pilot</title><season>1</season><episode>1</episode><aired>2004-09-22</aired>
+            <uniqueid type=\"tvdb\" default=\"true\">127131</uniqueid>
+        </episodedetails>").unwrap();
+        nfo.inherit(Some(Nfo::from_xml("<tvshow><title>Lost</title></tvshow>").unwrap()), None, crate::nfo::Inherit::EpisodeWins);
+        let back = tags_to_nfo(&map_tags(&MappingTable::mkv(), &nfo));
+        assert_eq!(back.kind, NfoKind::Episode);
+        assert_eq!((back.title.as_str(), back.showtitle.as_str()), ("Pilot", "Lost"));
+        assert_eq!((back.season, back.episode), (Some(1), Some(1)));
+        assert_eq!(back.release_date(), "2004-09-22");
+        assert_eq!(back.tvdb_id(), Some("127131"));
+        assert!(back.collection().is_none());
+    }
+
+    #[test]
+    fn reads_back_the_legacy_names() {
+        let nfo = Nfo::from_xml(MOVIE).unwrap();
+        let tags = map_tags(&MappingTable::preset("mkv", Some("legacy")).unwrap(), &nfo);
+        assert_eq!(tags.get("Director"), Some("Michael Mann"));
+        assert_eq!(tags.get("Actor"), Some("Al Pacino,Robert De Niro,Val Kilmer"));
+        assert_eq!(tags.get("Collection Name"), Some("Mann Collection"));
+
+        let back = tags_to_nfo(&tags);
+        assert_eq!(back.kind, NfoKind::Movie);
+        assert_eq!(back.title, "Heat");
+        assert_eq!(back.directors, ["Michael Mann"]);
+        let names: Vec<&str> = back.actors_in_order().iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Al Pacino", "Robert De Niro", "Val Kilmer"]);
+        let set = back.collection().unwrap();
+        assert_eq!((set.name(), set.overview.as_str()), ("Mann Collection", "Films by Michael Mann."));
+        assert_eq!(back.plot, nfo.plot);
+        assert_eq!(back.studios, nfo.studios);
+        assert_eq!(back.imdb_id(), Some("tt0113277"));
+        assert_eq!(back.year, Some(1995));
+    }
+}
 
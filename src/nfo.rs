@@ -0,0 +1,1199 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+/// The root element an NFO was written with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NfoKind {
+    #[default]
+    Movie,
+    TvShow,
+    Episode,
+    MusicVideo,
+    /// A season.nfo in a show's season folder.
+    Season,
+}
+
+impl NfoKind {
+    pub fn from_root(name: &str) -> Option<NfoKind> {
+        match name {
+            "movie" => Some(NfoKind::Movie),
+            "tvshow" => Some(NfoKind::TvShow),
+            "episodedetails" => Some(NfoKind::Episode),
+            "musicvideo" => Some(NfoKind::MusicVideo),
+            "season" => Some(NfoKind::Season),
+            _ => None,
+        }
+    }
+
+    pub fn root_name(&self) -> &'static str {
+        match self {
+            NfoKind::Movie => "movie",
+            NfoKind::TvShow => "tvshow",
+            NfoKind::Episode => "episodedetails",
+            NfoKind::MusicVideo => "musicvideo",
+            NfoKind::Season => "season",
+        }
+    }
+}
+
+/// How an episode combines with its show's tvshow.nfo and season.nfo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Inherit {
+    /// The episode's own values win; the show and season fill in what it lacks.
+    #[default]
+    EpisodeWins,
+    /// The show's and season's values replace the episode's.
+    ShowWins,
+    /// Ignore tvshow.nfo and season.nfo.
+    None,
+}
+
+impl Inherit {
+    pub const NAMES: [&'static str; 3] = ["episode-wins", "show-wins", "none"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Inherit::EpisodeWins => "episode-wins",
+            Inherit::ShowWins => "show-wins",
+            Inherit::None => "none",
+        }
+    }
+}
+
+impl std::fmt::Display for Inherit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Inherit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "episode-wins" => Ok(Inherit::EpisodeWins),
+            "show-wins" => Ok(Inherit::ShowWins),
+            "none" => Ok(Inherit::None),
+            _ => Err(format!("unknown inherit policy {}, expected one of {}", s, Inherit::NAMES.join(", "))),
+        }
+    }
+}
+
+/// A repair [`Nfo::from_bytes`] made to read an NFO that isn't clean UTF-8
+/// XML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Recovery {
+    /// A UTF-8 byte order mark was dropped.
+    ByteOrderMark,
+    /// The file was UTF-16.
+    Utf16,
+    /// The file wasn't valid UTF-8 and was read as Windows-1252.
+    Windows1252,
+    /// Text outside the root element, usually a scraper URL, was ignored.
+    StrayText,
+    /// Ids were taken from an IMDb or TMDb URL in the stray text.
+    UrlIds,
+    /// The NFO was nothing but a URL; only its ids were read.
+    UrlOnly,
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Recovery::ByteOrderMark => "dropped a byte order mark",
+            Recovery::Utf16 => "decoded UTF-16",
+            Recovery::Windows1252 => "decoded Windows-1252",
+            Recovery::StrayText => "ignored text outside the XML",
+            Recovery::UrlIds => "took ids from a URL",
+            Recovery::UrlOnly => "read ids from a URL-only NFO",
+        })
+    }
+}
+
+/// Kodi NFO as written by Kodi and tinyMediaManager. One struct covers
+/// movie, tvshow, episodedetails and musicvideo; fields a kind does not use
+/// are left empty.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Nfo {
+    #[serde(skip)]
+    pub kind: NfoKind,
+    pub title: String,
+    pub originaltitle: String,
+    pub sorttitle: String,
+    pub showtitle: String,
+    pub ratings: Ratings,
+    #[serde(deserialize_with = "lenient")]
+    pub userrating: Option<f32>,
+    #[serde(deserialize_with = "lenient")]
+    pub top250: Option<u32>,
+    pub outline: String,
+    pub plot: String,
+    pub tagline: String,
+    #[serde(deserialize_with = "lenient")]
+    pub runtime: Option<u32>,
+    #[serde(rename = "thumb")]
+    pub thumbs: Vec<Thumb>,
+    pub fanart: Fanart,
+    pub mpaa: String,
+    pub certification: String,
+    #[serde(deserialize_with = "lenient")]
+    pub playcount: Option<u32>,
+    pub lastplayed: String,
+    pub id: String,
+    #[serde(rename = "uniqueid")]
+    pub unique_ids: Vec<UniqueId>,
+    pub tmdbid: String,
+    pub tvdbid: String,
+    #[serde(rename = "genre")]
+    pub genres: Vec<String>,
+    #[serde(rename = "tag")]
+    pub tags: Vec<String>,
+    #[serde(rename = "country")]
+    pub countries: Vec<String>,
+    pub set: Option<MovieSet>,
+    pub credits: Vec<String>,
+    #[serde(rename = "director")]
+    pub directors: Vec<String>,
+    pub premiered: String,
+    #[serde(deserialize_with = "lenient")]
+    pub year: Option<u32>,
+    pub status: String,
+    pub code: String,
+    pub aired: String,
+    #[serde(rename = "studio")]
+    pub studios: Vec<String>,
+    pub trailer: String,
+    pub fileinfo: Option<FileInfo>,
+    #[serde(rename = "actor")]
+    pub actors: Vec<Person>,
+    #[serde(rename = "producer")]
+    pub producers: Vec<Person>,
+    pub dateadded: String,
+    #[serde(deserialize_with = "lenient")]
+    pub season: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub episode: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub displayseason: Option<i32>,
+    #[serde(deserialize_with = "lenient")]
+    pub displayepisode: Option<i32>,
+    pub episodeguide: String,
+    #[serde(rename = "namedseason")]
+    pub named_seasons: Vec<NamedSeason>,
+    pub album: String,
+    #[serde(rename = "artist")]
+    pub artists: Vec<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub track: Option<u32>,
+    /// The show's tvshow.nfo, once an episode has inherited from it.
+    #[serde(skip)]
+    pub tvshow: Option<Box<Nfo>>,
+    /// The episode's season.nfo, or its `<namedseason>` from tvshow.nfo.
+    #[serde(skip)]
+    pub season_nfo: Option<Box<Nfo>>,
+    /// The other episodes of a multi-episode file, from the `<episodedetails>`
+    /// roots after the first.
+    #[serde(skip)]
+    pub more_episodes: Vec<Nfo>,
+    /// What had to be repaired to read the file, if anything.
+    #[serde(skip)]
+    pub recovered: Vec<Recovery>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Ratings {
+    #[serde(rename = "rating")]
+    pub ratings: Vec<Rating>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Rating {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@max", deserialize_with = "lenient")]
+    pub max: Option<u32>,
+    #[serde(rename = "@default", deserialize_with = "flag")]
+    pub default: bool,
+    #[serde(deserialize_with = "lenient")]
+    pub value: Option<f32>,
+    #[serde(deserialize_with = "lenient")]
+    pub votes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Thumb {
+    #[serde(rename = "@aspect")]
+    pub aspect: String,
+    #[serde(rename = "@preview")]
+    pub preview: String,
+    #[serde(rename = "@season")]
+    pub season: String,
+    #[serde(rename = "@type")]
+    pub thumb_type: String,
+    #[serde(rename = "$text")]
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Fanart {
+    #[serde(rename = "thumb")]
+    pub thumbs: Vec<Thumb>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UniqueId {
+    #[serde(rename = "@type")]
+    pub id_type: String,
+    #[serde(rename = "@default", deserialize_with = "flag")]
+    pub default: bool,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+/// `<set>` is either `<set><name/><overview/></set>` or, in older files,
+/// just the collection name as text.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MovieSet {
+    pub name: String,
+    pub overview: String,
+    #[serde(rename = "$text")]
+    text: String,
+}
+
+impl MovieSet {
+    pub fn new(name: &str, overview: &str) -> MovieSet {
+        MovieSet { name: name.to_string(), overview: overview.to_string(), text: String::new() }
+    }
+
+    pub fn name(&self) -> &str {
+        if self.name.is_empty() { self.text.trim() } else { &self.name }
+    }
+}
+
+/// An `<actor>` or `<producer>` entry.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Person {
+    pub name: String,
+    pub role: String,
+    #[serde(deserialize_with = "lenient")]
+    pub order: Option<u32>,
+    pub thumb: String,
+    pub profile: String,
+    pub tmdbid: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NamedSeason {
+    #[serde(rename = "@number", deserialize_with = "lenient")]
+    pub number: Option<u32>,
+    #[serde(rename = "$text")]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FileInfo {
+    pub streamdetails: StreamDetails,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StreamDetails {
+    pub video: Vec<VideoStream>,
+    pub audio: Vec<AudioStream>,
+    pub subtitle: Vec<SubtitleStream>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VideoStream {
+    pub codec: String,
+    #[serde(deserialize_with = "lenient")]
+    pub aspect: Option<f32>,
+    #[serde(deserialize_with = "lenient")]
+    pub width: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub height: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub durationinseconds: Option<u32>,
+    pub stereomode: String,
+    pub hdrtype: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AudioStream {
+    pub codec: String,
+    pub language: String,
+    #[serde(deserialize_with = "lenient")]
+    pub channels: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SubtitleStream {
+    pub language: String,
+}
+
+impl Nfo {
+    pub fn from_file(path: &Path) -> io::Result<Nfo> {
+        Nfo::from_bytes(&fs::read(path)?)
+    }
+
+    /// Reads an NFO the way it is often found in the wild: with a byte order
+    /// mark, in UTF-16 or Windows-1252, with a scraper URL before or after the
+    /// XML, or as nothing but a URL. What it had to do is in
+    /// [`recovered`](Nfo::recovered).
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Nfo> {
+        let (content, recovered) = decode(bytes);
+        let mut nfo = match Nfo::from_xml(&content) {
+            Err(e) if !content.contains('<') => {
+                let ids = url_ids(&content);
+                if ids.is_empty() {
+                    return Err(e);
+                }
+                let kind = if content.contains("themoviedb.org/tv/") { NfoKind::TvShow } else { NfoKind::Movie };
+                Nfo { kind, unique_ids: ids, recovered: vec![Recovery::UrlOnly], ..Nfo::default() }
+            }
+            result => result?,
+        };
+        nfo.recovered.splice(0..0, recovered);
+        Ok(nfo)
+    }
+
+    /// Parses an NFO. tinyMediaManager writes one `<episodedetails>` root per
+    /// episode for a multi-episode file; the first becomes the NFO and the
+    /// rest [`more_episodes`](Nfo::more_episodes). Text around the roots is
+    /// ignored, apart from the ids in any IMDb or TMDb URL.
+    pub fn from_xml(content: &str) -> io::Result<Nfo> {
+        let (roots, stray) = root_elements(content)?;
+        let kind = NfoKind::from_root(&roots[0].0).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, format!("Failed to parse NFO: unknown root element <{}>", roots[0].0))
+        })?;
+        let parse = |content: &str| quick_xml::de::from_str::<Nfo>(content).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("Failed to parse NFO: {}", e))
+        });
+        let mut nfo = parse(roots[0].1)?;
+        nfo.kind = kind;
+        if kind == NfoKind::Episode {
+            for (_, text) in roots[1..].iter().filter(|(name, _)| NfoKind::from_root(name) == Some(NfoKind::Episode)) {
+                let mut episode = parse(text)?;
+                episode.kind = NfoKind::Episode;
+                nfo.more_episodes.push(episode);
+            }
+        }
+        if !stray.is_empty() {
+            nfo.recovered.push(Recovery::StrayText);
+            let mut added = false;
+            for id in url_ids(&stray.join("\n")) {
+                if nfo.unique_id(&id.id_type).is_none() {
+                    nfo.unique_ids.push(UniqueId { default: nfo.unique_ids.is_empty(), ..id });
+                    added = true;
+                }
+            }
+            if added {
+                nfo.recovered.push(Recovery::UrlIds);
+            }
+        }
+        Ok(nfo)
+    }
+
+    /// This NFO and the rest of its multi-episode file, in file order.
+    pub fn episodes(&self) -> impl Iterator<Item = &Nfo> {
+        std::iter::once(self).chain(&self.more_episodes)
+    }
+
+    /// Fills an episode in from its show and season. Only what describes the
+    /// whole show is inherited: the show title, genres, studios, ratings,
+    /// countries, tags and cast. Ids, titles and plots stay the episode's own;
+    /// the parents are kept for `tvshow.*` and `season.*` mapping fields.
+    pub fn inherit(&mut self, tvshow: Option<Nfo>, season: Option<Nfo>, policy: Inherit) {
+        if policy == Inherit::None {
+            return;
+        }
+        let season = season.or_else(|| {
+            let number = self.season?;
+            let named = tvshow.as_ref()?.named_seasons.iter().find(|s| s.number == Some(number))?;
+            Some(Nfo { kind: NfoKind::Season, title: named.name.clone(), ..Nfo::default() })
+        });
+        let parents: Vec<&Nfo> = season.iter().chain(tvshow.iter()).collect();
+        let wins = policy == Inherit::ShowWins;
+        if let Some(show) = &tvshow {
+            inherit_field(&mut self.showtitle, &[&show.title], wins);
+        }
+        inherit_field(&mut self.genres, &parents.iter().map(|p| &p.genres).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.studios, &parents.iter().map(|p| &p.studios).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.mpaa, &parents.iter().map(|p| &p.mpaa).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.certification, &parents.iter().map(|p| &p.certification).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.countries, &parents.iter().map(|p| &p.countries).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.tags, &parents.iter().map(|p| &p.tags).collect::<Vec<_>>(), wins);
+        inherit_field(&mut self.actors, &parents.iter().map(|p| &p.actors).collect::<Vec<_>>(), wins);
+        for episode in &mut self.more_episodes {
+            episode.inherit(tvshow.clone(), season.clone(), policy);
+        }
+        self.tvshow = tvshow.map(Box::new);
+        self.season_nfo = season.map(Box::new);
+    }
+
+    pub fn is_tv_episode(&self) -> bool {
+        self.kind == NfoKind::Episode
+            || !self.showtitle.is_empty() || self.season.is_some() || self.episode.is_some()
+    }
+
+    /// Looks up a `<uniqueid>` by type, e.g. "imdb", "tmdb" or "tvdb".
+    pub fn unique_id(&self, id_type: &str) -> Option<&str> {
+        self.unique_ids.iter()
+            .find(|u| u.id_type.eq_ignore_ascii_case(id_type) && !u.value.trim().is_empty())
+            .map(|u| u.value.trim())
+    }
+
+    pub fn default_unique_id(&self) -> Option<&UniqueId> {
+        self.unique_ids.iter().find(|u| u.default)
+    }
+
+    pub fn imdb_id(&self) -> Option<&str> {
+        // uniqueid first (TV episodes and newer files), then the older <id> field
+        if let Some(id) = self.unique_id("imdb") {
+            return Some(id);
+        }
+        let id = self.id.trim();
+        if id.starts_with("tt") { Some(id) } else { None }
+    }
+
+    pub fn tmdb_id(&self) -> Option<&str> {
+        self.unique_id("tmdb").or_else(|| non_empty(&self.tmdbid))
+    }
+
+    pub fn tvdb_id(&self) -> Option<&str> {
+        self.unique_id("tvdb").or_else(|| non_empty(&self.tvdbid))
+    }
+
+    /// The rating flagged `default="true"`, or the first one listed.
+    pub fn default_rating(&self) -> Option<&Rating> {
+        self.ratings.ratings.iter().find(|r| r.default).or_else(|| self.ratings.ratings.first())
+    }
+
+    /// Episodes carry `aired`, movies carry `premiered`.
+    pub fn release_date(&self) -> &str {
+        if !self.aired.is_empty() { &self.aired } else { &self.premiered }
+    }
+
+    pub fn collection(&self) -> Option<&MovieSet> {
+        self.set.as_ref().filter(|s| !s.name().is_empty())
+    }
+
+    /// Actors sorted by `<order>`; entries without an order keep file order at the end.
+    pub fn actors_in_order(&self) -> Vec<&Person> {
+        let mut actors: Vec<&Person> = self.actors.iter().filter(|a| !a.name.trim().is_empty()).collect();
+        actors.sort_by_key(|a| a.order.unwrap_or(u32::MAX));
+        actors
+    }
+
+    /// Serializes the NFO as Kodi XML. Empty fields are left out, as are
+    /// artwork and stream details. Each of [`more_episodes`](Nfo::more_episodes)
+    /// follows as another root.
+    pub fn to_xml(&self) -> io::Result<String> {
+        let mut xml = self.root_xml()?;
+        for episode in &self.more_episodes {
+            let more = episode.root_xml()?;
+            xml.push('\n');
+            xml.push_str(more.split_once("?>").map_or(more.as_str(), |(_, root)| root.trim_start()));
+        }
+        Ok(xml)
+    }
+
+    fn root_xml(&self) -> io::Result<String> {
+        let mut nfo = NfoWriter::new();
+        nfo.start(self.kind.root_name(), &[])?;
+        nfo.text("title", &self.title)?;
+        nfo.text("originaltitle", &self.originaltitle)?;
+        nfo.text("sorttitle", &self.sorttitle)?;
+        nfo.text("showtitle", &self.showtitle)?;
+        if !self.ratings.ratings.is_empty() {
+            nfo.start("ratings", &[])?;
+            for rating in &self.ratings.ratings {
+                let max = rating.max.map(|m| m.to_string()).unwrap_or_default();
+                let default = if rating.default { "true" } else { "false" };
+                nfo.start("rating", &[("name", &rating.name), ("max", &max), ("default", default)])?;
+                nfo.number("value", rating.value)?;
+                nfo.number("votes", rating.votes)?;
+                nfo.end()?;
+            }
+            nfo.end()?;
+        }
+        nfo.number("userrating", self.userrating)?;
+        nfo.number("top250", self.top250)?;
+        nfo.number("season", self.season)?;
+        nfo.number("episode", self.episode)?;
+        nfo.number("displayseason", self.displayseason)?;
+        nfo.number("displayepisode", self.displayepisode)?;
+        nfo.text("outline", &self.outline)?;
+        nfo.text("plot", &self.plot)?;
+        nfo.text("tagline", &self.tagline)?;
+        nfo.number("runtime", self.runtime)?;
+        nfo.text("mpaa", &self.mpaa)?;
+        nfo.text("certification", &self.certification)?;
+        nfo.number("playcount", self.playcount)?;
+        nfo.text("lastplayed", &self.lastplayed)?;
+        nfo.text("id", &self.id)?;
+        for unique_id in self.unique_ids.iter().filter(|u| !u.value.trim().is_empty()) {
+            let default = if unique_id.default { "true" } else { "false" };
+            nfo.start("uniqueid", &[("type", &unique_id.id_type), ("default", default)])?;
+            nfo.characters(&unique_id.value)?;
+            nfo.end()?;
+        }
+        nfo.list("genre", &self.genres)?;
+        nfo.list("tag", &self.tags)?;
+        nfo.list("country", &self.countries)?;
+        if let Some(set) = self.collection() {
+            nfo.start("set", &[])?;
+            nfo.text("name", set.name())?;
+            nfo.text("overview", &set.overview)?;
+            nfo.end()?;
+        }
+        nfo.list("credits", &self.credits)?;
+        nfo.list("director", &self.directors)?;
+        nfo.text("premiered", &self.premiered)?;
+        nfo.number("year", self.year)?;
+        nfo.text("status", &self.status)?;
+        nfo.text("code", &self.code)?;
+        nfo.text("aired", &self.aired)?;
+        nfo.list("studio", &self.studios)?;
+        nfo.text("trailer", &self.trailer)?;
+        for (element, people) in [("actor", &self.actors), ("producer", &self.producers)] {
+            for person in people.iter().filter(|p| !p.name.trim().is_empty()) {
+                nfo.start(element, &[])?;
+                nfo.text("name", &person.name)?;
+                nfo.text("role", &person.role)?;
+                nfo.number("order", person.order)?;
+                nfo.text("thumb", &person.thumb)?;
+                nfo.text("profile", &person.profile)?;
+                nfo.text("tmdbid", &person.tmdbid)?;
+                nfo.end()?;
+            }
+        }
+        nfo.text("album", &self.album)?;
+        nfo.list("artist", &self.artists)?;
+        nfo.number("track", self.track)?;
+        nfo.text("dateadded", &self.dateadded)?;
+        nfo.end()?;
+        nfo.finish()
+    }
+
+    pub fn write_file(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_xml()?)
+    }
+}
+
+/// Thin wrapper over the xml crate's writer that skips empty values.
+struct NfoWriter {
+    writer: EventWriter<Vec<u8>>,
+}
+
+fn write_error(e: xml::writer::Error) -> Error {
+    Error::other(format!("Failed to write NFO: {}", e))
+}
+
+impl NfoWriter {
+    fn new() -> Self {
+        let config = EmitterConfig::new().perform_indent(true);
+        NfoWriter { writer: EventWriter::new_with_config(Vec::new(), config) }
+    }
+
+    fn start(&mut self, name: &str, attributes: &[(&str, &str)]) -> io::Result<()> {
+        let mut element = XmlEvent::start_element(name);
+        for (key, value) in attributes.iter().filter(|(_, v)| !v.is_empty()) {
+            element = element.attr(*key, value);
+        }
+        self.writer.write(element).map_err(write_error)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.writer.write(XmlEvent::end_element()).map_err(write_error)
+    }
+
+    fn characters(&mut self, value: &str) -> io::Result<()> {
+        self.writer.write(XmlEvent::characters(value.trim())).map_err(write_error)
+    }
+
+    fn text(&mut self, name: &str, value: &str) -> io::Result<()> {
+        if value.trim().is_empty() {
+            return Ok(());
+        }
+        self.start(name, &[])?;
+        self.characters(value)?;
+        self.end()
+    }
+
+    fn number<T: ToString>(&mut self, name: &str, value: Option<T>) -> io::Result<()> {
+        match value {
+            Some(value) => self.text(name, &value.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn list(&mut self, name: &str, values: &[String]) -> io::Result<()> {
+        for value in values {
+            self.text(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<String> {
+        String::from_utf8(self.writer.into_inner()).map_err(|e| Error::other(format!("Failed to write NFO: {}", e)))
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+/// Something that can be missing from an NFO, like an empty list or string.
+trait Blank {
+    fn is_blank(&self) -> bool;
+}
+
+impl Blank for String {
+    fn is_blank(&self) -> bool {
+        self.trim().is_empty()
+    }
+}
+
+impl<T> Blank for Vec<T> {
+    fn is_blank(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+/// Takes the first of `parents` that has a value, when `own` has none or
+/// `parents_win`.
+fn inherit_field<T: Blank + Clone>(own: &mut T, parents: &[&T], parents_win: bool) {
+    if !parents_win && !own.is_blank() {
+        return;
+    }
+    if let Some(value) = parents.iter().find(|p| !p.is_blank()) {
+        *own = (*value).clone();
+    }
+}
+
+/// A top-level element's name and the text it spans.
+type Root<'a> = (String, &'a str);
+
+/// The top-level elements of an NFO, each with its name and the text it
+/// spans, and any text outside them. Anything unreadable after the first
+/// root counts as such text.
+fn root_elements(content: &str) -> io::Result<(Vec<Root<'_>>, Vec<&str>)> {
+    let mut reader = Reader::from_str(content);
+    let mut roots = Vec::new();
+    let mut stray = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut name = String::new();
+    loop {
+        let before = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if depth == 0 {
+                    start = before;
+                    name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                }
+                depth += 1;
+            }
+            Ok(Event::Empty(e)) if depth == 0 => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                roots.push((name, &content[before..reader.buffer_position() as usize]));
+            }
+            Ok(Event::End(_)) => {
+                depth -= 1;
+                if depth == 0 {
+                    roots.push((std::mem::take(&mut name), &content[start..reader.buffer_position() as usize]));
+                }
+            }
+            Ok(Event::Text(_)) if depth == 0 => {
+                let text = content[before..reader.buffer_position() as usize].trim();
+                if !text.is_empty() {
+                    stray.push(text);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) if roots.is_empty() && depth == 0 => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Failed to parse NFO: {}", e)));
+            }
+            Err(_) if depth == 0 => {
+                stray.push(content[before..].trim());
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+    // An unfinished first root is left for the deserializer to report.
+    if roots.is_empty() && depth > 0 {
+        roots.push((name, &content[start..]));
+    }
+    if roots.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "Failed to parse NFO: no root element"));
+    }
+    Ok((roots, stray))
+}
+
+/// Decodes an NFO's bytes, telling UTF-16 from its byte order mark or its
+/// first `<`, and falling back to Windows-1252 when it isn't UTF-8.
+fn decode(bytes: &[u8]) -> (String, Vec<Recovery>) {
+    let utf16 = |bytes: &[u8], unit: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| unit([c[0], c[1]])).collect();
+        (String::from_utf16_lossy(&units), vec![Recovery::Utf16])
+    };
+    match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => {
+            let (text, mut recovered) = decode(rest);
+            recovered.insert(0, Recovery::ByteOrderMark);
+            (text, recovered)
+        }
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [b'<', 0, ..] => utf16(bytes, u16::from_le_bytes),
+        [0, b'<', ..] => utf16(bytes, u16::from_be_bytes),
+        _ => match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), Vec::new()),
+            Err(_) => (bytes.iter().map(|b| windows_1252(*b)).collect(), vec![Recovery::Windows1252]),
+        },
+    }
+}
+
+/// Windows-1252 puts punctuation and a few letters where Latin-1 has
+/// control codes; everything else is the same code point.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+        '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// The ids in IMDb and TMDb URLs such as `https://www.imdb.com/title/tt0133093/`
+/// or `https://www.themoviedb.org/movie/603-the-matrix`.
+fn url_ids(text: &str) -> Vec<UniqueId> {
+    let mut ids: Vec<UniqueId> = Vec::new();
+    let sites = [("imdb", "imdb.com/title/"), ("tmdb", "themoviedb.org/movie/"), ("tmdb", "themoviedb.org/tv/")];
+    for (id_type, marker) in sites {
+        for (at, _) in text.match_indices(marker) {
+            let rest = &text[at + marker.len()..];
+            let digits = |text: &str| Some(text.chars().take_while(char::is_ascii_digit).collect::<String>())
+                .filter(|d| !d.is_empty());
+            let id = match id_type {
+                "imdb" => rest.strip_prefix("tt").and_then(digits).map(|d| format!("tt{}", d)),
+                _ => digits(rest),
+            };
+            if let Some(value) = id.filter(|_| !ids.iter().any(|i| i.id_type == id_type)) {
+                ids.push(UniqueId { id_type: id_type.to_string(), default: ids.is_empty(), value });
+            }
+        }
+    }
+    ids
+}
+
+/// Parses numbers from text, treating empty or malformed values as missing.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+{
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    Ok(value.and_then(|v| v.trim().parse().ok()))
+}
+
+fn flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<String> = Option::deserialize(deserializer)?;
+    Ok(value.is_some_and(|v| v.trim().eq_ignore_ascii_case("true")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOVIE: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<movie><title>Amélie</title></movie>\n";
+
+    fn utf16(text: &str, bom: bool, unit: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = if bom { unit(0xFEFF).to_vec() } else { Vec::new() };
+        bytes.extend(text.encode_utf16().flat_map(unit));
+        bytes
+    }
+
+    fn ids(nfo: &Nfo) -> Vec<(&str, &str, bool)> {
+        nfo.unique_ids.iter().map(|u| (u.id_type.as_str(), u.value.as_str(), u.default)).collect()
+    }
+
+    #[test]
+    fn clean_utf8_needs_no_recovery() {
+        let nfo = Nfo::from_bytes(MOVIE.as_bytes()).unwrap();
+        assert_eq!(nfo.title, "Amélie");
+        assert!(nfo.recovered.is_empty());
+    }
+
+    #[test]
+    fn drops_a_byte_order_mark() {
+        let bytes = [b"\xEF\xBB\xBF".as_slice(), MOVIE.as_bytes()].concat();
+        let nfo = Nfo::from_bytes(&bytes).unwrap();
+        assert_eq!(nfo.title, "Amélie");
+        assert_eq!(nfo.recovered, [Recovery::ByteOrderMark]);
+    }
+
+    #[test]
+    fn decodes_utf16_with_and_without_a_byte_order_mark() {
+        let xml = MOVIE.trim_start_matches(|c| c != '\n').trim_start();
+        for bom in [true, false] {
+            for unit in [u16::to_le_bytes, u16::to_be_bytes] {
+                let text = if bom { MOVIE } else { xml };
+                let nfo = Nfo::from_bytes(&utf16(text, bom, unit)).unwrap();
+                assert_eq!(nfo.title, "Amélie");
+                assert_eq!(nfo.recovered, [Recovery::Utf16]);
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_to_windows_1252() {
+        let nfo = Nfo::from_bytes(b"<movie><title>Am\xE9lie \x96 \x93Le Fabuleux\x94</title></movie>").unwrap();
+        assert_eq!(nfo.title, "Amélie – “Le Fabuleux”");
+        assert_eq!(nfo.recovered, [Recovery::Windows1252]);
+    }
+
+    #[test]
+    fn takes_ids_from_a_trailing_imdb_url() {
+        let text = format!("{}https://www.imdb.com/title/tt0211915/\n", MOVIE);
+        let nfo = Nfo::from_bytes(text.as_bytes()).unwrap();
+        assert_eq!(nfo.title, "Amélie");
+        assert_eq!(ids(&nfo), [("imdb", "tt0211915", true)]);
+        assert_eq!(nfo.recovered, [Recovery::StrayText, Recovery::UrlIds]);
+    }
+
+    #[test]
+    fn url_ids_do_not_replace_the_nfo_ids() {
+        let xml = "<movie><uniqueid type=\"imdb\" default=\"true\">tt0211915</uniqueid></movie>\n\
+                   https://www.imdb.com/title/tt9999999/ https://www.themoviedb.org/movie/194-amelie";
+        let nfo = Nfo::from_xml(xml).unwrap();
+        assert_eq!(ids(&nfo), [("imdb", "tt0211915", true), ("tmdb", "194", false)]);
+        assert_eq!(nfo.recovered, [Recovery::StrayText, Recovery::UrlIds]);
+    }
+
+    #[test]
+    fn reads_a_url_only_nfo() {
+        let nfo = Nfo::from_bytes(b"https://www.themoviedb.org/movie/194-amelie\n").unwrap();
+        assert_eq!(nfo.kind, NfoKind::Movie);
+        assert_eq!(ids(&nfo), [("tmdb", "194", true)]);
+        assert_eq!(nfo.recovered, [Recovery::UrlOnly]);
+
+        let nfo = Nfo::from_bytes(b"https://www.themoviedb.org/tv/1399").unwrap();
+        assert_eq!(nfo.kind, NfoKind::TvShow);
+
+        assert!(Nfo::from_bytes(b"nothing to see here").is_err());
+    }
+
+    #[test]
+    fn url_ids_reads_each_site_once() {
+        let text = "imdb.com/title/tt0133093/ imdb.com/title/tt0234215/ themoviedb.org/movie/603-the-matrix imdb.com/title/nope";
+        let found = url_ids(text);
+        let found: Vec<(&str, &str, bool)> = found.iter().map(|u| (u.id_type.as_str(), u.value.as_str(), u.default)).collect();
+        assert_eq!(found, [("imdb", "tt0133093", true), ("tmdb", "603", false)]);
+        assert!(url_ids("imdb.com/title/tt").is_empty());
+    }
+
+    #[test]
+    fn root_elements_splits_roots_from_stray_text() {
+        let (roots, stray) = root_elements("junk\n<movie><title>A</title></movie>\n<extra/>\ntrailing").unwrap();
+        let names: Vec<&str> = roots.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["movie", "extra"]);
+        assert_eq!(roots[0].1, "<movie><title>A</title></movie>");
+        assert_eq!(stray, ["junk", "trailing"]);
+    }
+
+    #[test]
+    fn unclosed_root_is_an_error() {
+        let (roots, stray) = root_elements("<movie><title>A</title>").unwrap();
+        assert_eq!(roots[0], ("movie".to_string(), "<movie><title>A</title>"));
+        assert!(stray.is_empty());
+        assert!(Nfo::from_bytes(b"<movie><title>A</title>").is_err());
+        assert!(root_elements("").is_err());
+    }
+
+    const FULL_MOVIE: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<movie>
+    <title>Heat</title>
+    <originaltitle>Heat</originaltitle>
+    <ratings>
+        <rating name="imdb" max="10">
+            <value>8.3</value>
+            <votes>712000</votes>
+        </rating>
+        <rating name="themoviedb" max="10" default="true">
+            <value>7.9</value>
+            <votes>6500</votes>
+        </rating>
+    </ratings>
+    <userrating>9</userrating>
+    <runtime>170</runtime>
+    <plot>A group of professional bank robbers.</plot>
+    <uniqueid type="tmdb">949</uniqueid>
+    <uniqueid type="imdb" default="true">tt0113277</uniqueid>
+    <genre>Crime</genre>
+    <genre>Drama</genre>
+    <set>
+        <name>Mann Collection</name>
+        <overview>Films by Michael Mann.</overview>
+    </set>
+    <director>Michael Mann</director>
+    <premiered>1995-12-15</premiered>
+    <year>1995</year>
+    <studio>Warner Bros.</studio>
+    <actor>
+        <name>Robert De Niro</name>
+        <role>Neil McCauley</role>
+        <order>1</order>
+    </actor>
+    <actor>
+        <name>Val Kilmer</name>
+        <role>Chris Shiherlis</role>
+    </actor>
+    <actor>
+        <name>Al Pacino</name>
+        <role>Vincent Hanna</role>
+        <order>0</order>
+    </actor>
+    <fileinfo>
+        <streamdetails>
+            <video><codec>h264</codec><width>1920</width><height>800</height></video>
+        </streamdetails>
+    </fileinfo>
+</movie>
+"#;
+
+    fn names(people: &[&Person]) -> Vec<String> {
+        people.iter().map(|p| p.name.clone()).collect()
+    }
+
+    #[test]
+    fn parses_a_movie() {
+        let nfo = Nfo::from_xml(FULL_MOVIE).unwrap();
+        assert_eq!(nfo.kind, NfoKind::Movie);
+        assert!(!nfo.is_tv_episode());
+        assert_eq!(nfo.title, "Heat");
+        assert_eq!(nfo.runtime, Some(170));
+        assert_eq!(nfo.year, Some(1995));
+        assert_eq!(nfo.genres, ["Crime", "Drama"]);
+        assert_eq!(nfo.fileinfo.unwrap().streamdetails.video[0].width, Some(1920));
+    }
+
+    #[test]
+    fn picks_the_default_rating_and_uniqueid() {
+        let nfo = Nfo::from_xml(FULL_MOVIE).unwrap();
+        let rating = nfo.default_rating().unwrap();
+        assert_eq!((rating.name.as_str(), rating.value, rating.votes, rating.max), ("themoviedb", Some(7.9), Some(6500), Some(10)));
+        assert!(!nfo.ratings.ratings[0].default);
+
+        assert_eq!(nfo.default_unique_id().unwrap().id_type, "imdb");
+        assert!(!nfo.unique_ids[0].default);
+        assert_eq!(nfo.imdb_id(), Some("tt0113277"));
+        assert_eq!(nfo.tmdb_id(), Some("949"));
+
+        // Without a flag the first rating is the default.
+        let nfo = Nfo::from_xml("<movie><ratings><rating name=\"imdb\"><value>7</value></rating></ratings></movie>").unwrap();
+        assert_eq!(nfo.default_rating().unwrap().name, "imdb");
+    }
+
+    #[test]
+    fn reads_both_forms_of_set() {
+        let nested = Nfo::from_xml(FULL_MOVIE).unwrap();
+        let set = nested.collection().unwrap();
+        assert_eq!((set.name(), set.overview.as_str()), ("Mann Collection", "Films by Michael Mann."));
+
+        let plain = Nfo::from_xml("<movie><set> Mann Collection </set></movie>").unwrap();
+        let set = plain.collection().unwrap();
+        assert_eq!((set.name(), set.overview.as_str()), ("Mann Collection", ""));
+
+        assert!(Nfo::from_xml("<movie><set></set></movie>").unwrap().collection().is_none());
+    }
+
+    #[test]
+    fn sorts_actors_by_order() {
+        let nfo = Nfo::from_xml(FULL_MOVIE).unwrap();
+        assert_eq!(names(&nfo.actors_in_order()), ["Al Pacino", "Robert De Niro", "Val Kilmer"]);
+    }
+
+    #[test]
+    fn parses_a_tvshow() {
+        let nfo = Nfo::from_xml(r#"<tvshow>
+            <title>Lost</title>
+            <uniqueid type="tvdb" default="true">73739</uniqueid>
+            <namedseason number="1">Season One</namedseason>
+            <premiered>2004-09-22</premiered>
+            <status>Ended</status>
+        </tvshow>"#).unwrap();
+        assert_eq!(nfo.kind, NfoKind::TvShow);
+        assert_eq!(nfo.tvdb_id(), Some("73739"));
+        assert_eq!(nfo.named_seasons[0].number, Some(1));
+        assert_eq!(nfo.named_seasons[0].name, "Season One");
+        assert_eq!(nfo.status, "Ended");
+    }
+
+    #[test]
+    fn parses_an_episode() {
+        let nfo = Nfo::from_xml(r#"<episodedetails>
+            <title>Pilot (1)</title>
+            <showtitle>Lost</showtitle>
+            <season>1</season>
+            <episode>1</episode>
+            <aired>2004-09-22</aired>
+            <premiered>2004-01-01</premiered>
+        </episodedetails>"#).unwrap();
+        assert_eq!(nfo.kind, NfoKind::Episode);
+        assert!(nfo.is_tv_episode());
+        assert_eq!((nfo.season, nfo.episode), (Some(1), Some(1)));
+        assert_eq!(nfo.release_date(), "2004-09-22");
+        assert!(nfo.more_episodes.is_empty());
+    }
+
+    #[test]
+    fn parses_a_musicvideo() {
+        let nfo = Nfo::from_xml(r#"<musicvideo>
+            <title>Hurt</title>
+            <album>American IV</album>
+            <artist>Johnny Cash</artist>
+            <track>2</track>
+            <year>abc</year>
+        </musicvideo>"#).unwrap();
+        assert_eq!(nfo.kind, NfoKind::MusicVideo);
+        assert_eq!(nfo.album, "American IV");
+        assert_eq!(nfo.artists, ["Johnny Cash"]);
+        assert_eq!(nfo.track, Some(2));
+        assert_eq!(nfo.year, None);
+    }
+
+    #[test]
+    fn unknown_root_is_an_error() {
+        assert!(Nfo::from_xml("<video><title>A</title></video>").is_err());
+    }
+
+    #[test]
+    fn to_xml_reads_back_the_same() {
+        let nfo = Nfo::from_xml(FULL_MOVIE).unwrap();
+        let xml = nfo.to_xml().unwrap();
+        let again = Nfo::from_xml(&xml).unwrap();
+        assert_eq!(again.kind, NfoKind::Movie);
+        assert_eq!(again.title, nfo.title);
+        assert_eq!(again.plot, nfo.plot);
+        assert_eq!(again.genres, nfo.genres);
+        assert_eq!(again.directors, nfo.directors);
+        assert_eq!(again.premiered, nfo.premiered);
+        assert_eq!(again.year, nfo.year);
+        assert_eq!(ids(&again), ids(&nfo));
+        assert_eq!(again.default_rating().unwrap().name, "themoviedb");
+        assert_eq!(again.ratings.ratings.len(), 2);
+        assert_eq!(again.collection().unwrap().name(), "Mann Collection");
+        assert_eq!(names(&again.actors_in_order()), names(&nfo.actors_in_order()));
+        assert_eq!(again.actors[2].role, "Vincent Hanna");
+        // Stream details are left out.
+        assert!(again.fileinfo.is_none());
+        assert_eq!(again.to_xml().unwrap(), xml);
+    }
+
+    fn family() -> (Nfo, Nfo, Nfo) {
+        let episode = Nfo::from_xml("<episodedetails>
+            <title>Pilot</title><season>1</season><episode>1</episode>
+            <genre>Mystery</genre><uniqueid type=\"tvdb\">127131</uniqueid>
+        </episodedetails>").unwrap();
+        let tvshow = Nfo::from_xml("<tvshow>
+            <title>Lost</title><genre>Drama</genre><studio>ABC</studio><mpaa>TV-14</mpaa>
+            <uniqueid type=\"tvdb\">73739</uniqueid><namedseason number=\"1\">The Crash</namedseason>
+            <actor><name>Matthew Fox</name></actor>
+        </tvshow>").unwrap();
+        let season = Nfo::from_xml("<season><title>Season One</title><mpaa>TV-PG</mpaa></season>").unwrap();
+        (episode, tvshow, season)
+    }
+
+    #[test]
+    fn episode_wins_fills_in_what_the_episode_lacks() {
+        let (mut episode, tvshow, season) = family();
+        episode.inherit(Some(tvshow), Some(season), Inherit::EpisodeWins);
+        assert_eq!(episode.showtitle, "Lost");
+        assert_eq!(episode.genres, ["Mystery"]);
+        assert_eq!(episode.studios, ["ABC"]);
+        // The season comes before the show.
+        assert_eq!(episode.mpaa, "TV-PG");
+        assert_eq!(episode.actors[0].name, "Matthew Fox");
+        // Ids and titles stay the episode's own.
+        assert_eq!(episode.title, "Pilot");
+        assert_eq!(episode.tvdb_id(), Some("127131"));
+        assert_eq!(episode.tvshow.as_ref().unwrap().title, "Lost");
+        assert_eq!(episode.season_nfo.as_ref().unwrap().title, "Season One");
+    }
+
+    #[test]
+    fn show_wins_replaces_the_episode_values() {
+        let (mut episode, tvshow, season) = family();
+        episode.showtitle = "LOST (2004)".to_string();
+        episode.inherit(Some(tvshow), Some(season), Inherit::ShowWins);
+        assert_eq!(episode.showtitle, "Lost");
+        assert_eq!(episode.genres, ["Drama"]);
+        assert_eq!(episode.mpaa, "TV-PG");
+        assert_eq!(episode.title, "Pilot");
+        assert_eq!(episode.tvdb_id(), Some("127131"));
+    }
+
+    #[test]
+    fn none_leaves_the_episode_alone() {
+        let (mut episode, tvshow, season) = family();
+        episode.inherit(Some(tvshow), Some(season), Inherit::None);
+        assert_eq!(episode.showtitle, "");
+        assert_eq!(episode.genres, ["Mystery"]);
+        assert!(episode.studios.is_empty());
+        assert!(episode.tvshow.is_none() && episode.season_nfo.is_none());
+    }
+
+    #[test]
+    fn season_name_comes_from_namedseason_without_a_season_nfo() {
+        let (mut episode, tvshow, _) = family();
+        let mut second = episode.clone();
+        episode.inherit(Some(tvshow.clone()), None, Inherit::EpisodeWins);
+        let season = episode.season_nfo.as_ref().unwrap();
+        assert_eq!((season.kind, season.title.as_str()), (NfoKind::Season, "The Crash"));
+
+        second.season = Some(2);
+        second.inherit(Some(tvshow), None, Inherit::EpisodeWins);
+        assert!(second.season_nfo.is_none());
+    }
+
+    #[test]
+    fn more_episodes_inherit_too() {
+        let (mut episode, tvshow, season) = family();
+        episode.more_episodes.push(Nfo { kind: NfoKind::Episode, season: Some(1), episode: Some(2), ..Nfo::default() });
+        episode.inherit(Some(tvshow), Some(season), Inherit::EpisodeWins);
+        assert_eq!(episode.more_episodes[0].showtitle, "Lost");
+        assert_eq!(episode.more_episodes[0].genres, ["Drama"]);
+    }
+}
+
@@ -0,0 +1,243 @@
+use crate::backend::{ContainerBackend, Registry};
+use crate::cover::Cover;
+use crate::job::{FileResult, Job, Only};
+use crate::nfo::{Inherit, Nfo, Recovery};
+use crate::provenance;
+use crate::tags::{MergePolicy, TagSet};
+use crate::verify::verify;
+use log::{info, warn};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Everything `process_file` would do to one video, worked out without
+/// touching it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub video: PathBuf,
+    pub output: PathBuf,
+    pub backend: &'static str,
+    pub nfo: Option<PathBuf>,
+    /// What had to be repaired to read the NFO.
+    pub recovered: Vec<Recovery>,
+    /// The tvshow.nfo and season.nfo an episode inherits from.
+    pub inherited: Vec<PathBuf>,
+    pub cover: Option<Cover>,
+    /// None when the tags are left alone.
+    pub tags: Option<TagSet>,
+    /// The output already has what this run would write.
+    pub up_to_date: bool,
+    pub actions: Vec<String>,
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.video.display(), self.backend)?;
+        if let Some(nfo) = &self.nfo {
+            match self.recovered.as_slice() {
+                [] => writeln!(f, "  NFO:   {}", nfo.display())?,
+                recovered => writeln!(f, "  NFO:   {} ({})", nfo.display(),
+                    recovered.iter().map(Recovery::to_string).collect::<Vec<_>>().join(", "))?,
+            }
+        }
+        for parent in &self.inherited {
+            writeln!(f, "  From:  {}", parent.display())?;
+        }
+        match &self.cover {
+            Some(cover) => writeln!(f, "  Cover: {} ({}, {})", cover.path.display(), cover.mime_type,
+                if cover.landscape { "landscape" } else { "portrait" })?,
+            None => writeln!(f, "  Cover: none")?,
+        }
+        match &self.tags {
+            Some(tags) => {
+                writeln!(f, "  Tags:")?;
+                for tag in tags.iter() {
+                    writeln!(f, "    {} = {}", tag.name, preview(&tag.value))?;
+                }
+            }
+            None => writeln!(f, "  Tags:  left alone")?,
+        }
+        writeln!(f, "  Actions:")?;
+        for action in &self.actions {
+            writeln!(f, "    - {}", action)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of a tag value, cut down for terminal output.
+fn preview(value: &str) -> String {
+    let flat = value.replace(['\n', '\r'], " ");
+    if flat.chars().count() > 70 {
+        format!("{}...", flat.chars().take(67).collect::<String>())
+    } else {
+        flat
+    }
+}
+
+/// Tags a video using the built-in MP4 and MKV backends.
+pub fn process_file(job: &Job) -> io::Result<FileResult> {
+    process_file_with(job, &Registry::default())
+}
+
+/// Works out what [`process_file`] would do, without writing anything.
+pub fn plan_file(job: &Job) -> io::Result<Plan> {
+    plan_file_with(job, &Registry::default())
+}
+
+pub fn plan_file_with(job: &Job, registry: &Registry) -> io::Result<Plan> {
+    prepare(job, registry).map(|(_, plan)| plan)
+}
+
+pub(crate) fn prepare<'r>(job: &Job, registry: &'r Registry) -> io::Result<(&'r dyn ContainerBackend, Plan)> {
+    let video_path = job.video.as_path();
+    let backend = registry.detect(video_path)?;
+    let nfo_path = job.nfo.as_deref().filter(|_| job.only != Some(Only::Cover));
+    let cover_path = job.cover.as_deref().filter(|_| job.only != Some(Only::Tags));
+
+    let mut nfo: Option<Nfo> = None;
+
+    if let Some(nfo_file_path) = nfo_path {
+        if !nfo_file_path.exists() {
+            warn!("  No NFO file found at provided address: {}", nfo_file_path.display());
+        } else {
+            nfo = Some(Nfo::from_file(nfo_file_path)?);
+            log_recovered(nfo_file_path, nfo.as_ref());
+        }
+    }
+
+    // Episodes take show-wide fields from tvshow.nfo and season.nfo.
+    let mut inherited = Vec::new();
+    if let Some(nfo) = nfo.as_mut().filter(|n| n.is_tv_episode() && job.inherit != Inherit::None) {
+        let mut load = |path: Option<&PathBuf>| path.and_then(|path| match Nfo::from_file(path) {
+            Ok(parent) => {
+                log_recovered(path, Some(&parent));
+                inherited.push(path.clone());
+                Some(parent)
+            }
+            Err(e) => {
+                warn!("  Ignoring {}: {}", path.display(), e);
+                None
+            }
+        });
+        let season = load(job.season_nfo.as_ref());
+        let tvshow = load(job.tvshow_nfo.as_ref());
+        nfo.inherit(tvshow, season, job.inherit);
+    }
+
+    let cover = cover_path.map(Cover::load).transpose()?;
+
+    match job.only {
+        Some(Only::Tags) if nfo.is_none() => {
+            warn!("  Not Processing: No NFO file for tags only: {}", video_path.display());
+            return Err(Error::new(ErrorKind::NotFound, "NFO is missing or invalid."));
+        }
+        Some(Only::Cover) if cover.is_none() => {
+            warn!("  Not Processing: No cover file for cover only: {}", video_path.display());
+            return Err(Error::new(ErrorKind::NotFound, "Cover is missing or invalid."));
+        }
+        _ if nfo.is_none() && cover.is_none() => {
+            warn!("  Not Processing: Due to no NFO nor cover file: {}",video_path.display());
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "NFO and Cover are missing or invalid."
+            ));
+        }
+        _ => {}
+    }
+
+    let output = backend.target(job);
+    let (tags, up_to_date, skip_reason) = match &nfo {
+        Some(nfo) => {
+            let mut tags = backend.tags_for(nfo);
+            let sources: Vec<&Path> = nfo_path.into_iter().chain(inherited.iter().map(PathBuf::as_path)).collect();
+            let stamp = provenance::stamp(&sources, cover.as_ref().map(|c| c.path.as_path()), &provenance::settings(backend, job))?;
+            let up_to_date = provenance::is_current(backend, &output, &stamp);
+            tags.push(backend.provenance_tag(), &stamp);
+            (Some(tags), up_to_date, "already tagged from this NFO and cover")
+        }
+        None => {
+            // Without an NFO only the cover is written; compare it directly.
+            let embedded = if output.exists() { backend.read_cover(&output).ok().flatten() } else { None };
+            let kept = job.merge == Some(MergePolicy::FillMissing) && embedded.is_some();
+            let same = match (&embedded, &cover) {
+                (Some(embedded), Some(cover)) => fs::read(&cover.path).is_ok_and(|data| data == embedded.data),
+                _ => false,
+            };
+            (None, kept || same, if kept { "already has cover art, which fill-missing keeps" } else { "already has this cover" })
+        }
+    };
+    let actions = if up_to_date && !job.force {
+        vec![format!("Skip {}: {}", output.display(), skip_reason)]
+    } else {
+        backend.describe(job, tags.as_ref(), cover.as_ref())
+    };
+
+    Ok((backend, Plan {
+        video: job.video.clone(),
+        output,
+        backend: backend.name(),
+        nfo: nfo_path.filter(|_| nfo.is_some()).map(Path::to_path_buf),
+        recovered: nfo.as_ref().map(|n| n.recovered.clone()).unwrap_or_default(),
+        inherited,
+        cover,
+        tags,
+        up_to_date,
+        actions,
+    }))
+}
+
+fn log_recovered(path: &Path, nfo: Option<&Nfo>) {
+    if let Some(nfo) = nfo.filter(|n| !n.recovered.is_empty()) {
+        let recovered: Vec<String> = nfo.recovered.iter().map(Recovery::to_string).collect();
+        warn!("  Read {} leniently: {}", path.display(), recovered.join(", "));
+    }
+}
+
+/// Tags a video with whichever backend in `registry` recognizes it. The result
+/// is read back and checked before success is reported or, with
+/// `delete_original`, the source is removed. Files already tagged from the
+/// same NFO and cover are skipped unless `job.force` is set.
+pub fn process_file_with(job: &Job, registry: &Registry) -> io::Result<FileResult> {
+    let (backend, plan) = prepare(job, registry)?;
+    if plan.up_to_date && !job.force {
+        info!("  Unchanged since last run, skipping: {}", plan.output.display());
+        return Ok(FileResult {
+            video: job.video.clone(),
+            output: plan.output,
+            nfo: job.nfo.clone(),
+            cover: job.cover.clone(),
+            skipped: true,
+        });
+    }
+    let source = backend.summarize(&job.video)?;
+
+    info!("  Starting to process: {}", job.video.display());
+    match (&plan.tags, job.merge) {
+        (Some(tags), Some(policy)) => backend.merge(job, tags, plan.cover.as_ref(), policy)?,
+        (Some(tags), None) if job.only == Some(Only::Tags) => backend.write_tags(job, tags)?,
+        (Some(tags), None) => backend.apply(job, tags, plan.cover.as_ref())?,
+        (None, _) => {
+            if let Some(cover) = &plan.cover {
+                backend.attach_cover(job, cover)?;
+            }
+        }
+    }
+    let target = backend.target(job);
+    verify(backend, job, &target, &source, plan.tags.as_ref(), plan.cover.is_some())?;
+    if job.delete_original && target != job.video {
+        fs::remove_file(&job.video)?;
+        info!("  Deleted original: {}", job.video.display());
+    }
+    info!("  Processing completed successfully: {}", target.display());
+
+    Ok(FileResult {
+        video: job.video.clone(),
+        output: target,
+        nfo: job.nfo.clone(),
+        cover: job.cover.clone(),
+        skipped: false,
+    })
+}
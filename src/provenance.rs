@@ -0,0 +1,85 @@
+//! A stamp embedded with the tags that records which NFO and artwork a file
+//! was tagged from, and with which settings, so a later run can skip files
+//! whose sources and settings are unchanged.
+
+use crate::backend::ContainerBackend;
+use crate::job::Job;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Builds the stamp for a job's sources: the tool version plus SHA-256 hashes
+/// of the NFO and cover files and of `settings`. An episode's NFO is hashed
+/// together with the tvshow.nfo and season.nfo it inherits from.
+pub fn stamp(nfos: &[&Path], cover: Option<&Path>, settings: &str) -> io::Result<String> {
+    let hash = |paths: &[&Path]| -> io::Result<String> {
+        match paths {
+            [] => Ok("none".to_string()),
+            paths => sha256_files(paths),
+        }
+    };
+    Ok(format!(
+        "nfo2tags {}; nfo={}; cover={}; settings={}",
+        env!("CARGO_PKG_VERSION"),
+        hash(nfos)?,
+        hash(cover.as_slice())?,
+        sha256_hex(Sha256::digest(settings.as_bytes()).as_slice())
+    ))
+}
+
+/// Everything besides the sources that changes what gets written: the
+/// backend's effective mapping table, after presets and profiles, and the
+/// job's merge policy, `--only` and inheritance.
+pub fn settings(backend: &dyn ContainerBackend, job: &Job) -> String {
+    format!("{:?}; merge={:?}; only={:?}; inherit={:?}", backend.mapping(), job.merge, job.only, job.inherit)
+}
+
+/// True if the file at `path` already carries `stamp`. Files that are missing
+/// or can't be read are never current.
+pub fn is_current(backend: &dyn ContainerBackend, path: &Path, stamp: &str) -> bool {
+    path.exists() && backend.read_tags(path)
+        .is_ok_and(|tags| tags.get(backend.provenance_tag()) == Some(stamp))
+}
+
+fn sha256_files(paths: &[&Path]) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    for path in paths {
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+    }
+    Ok(sha256_hex(hasher.finalize().as_slice()))
+}
+
+fn sha256_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MkvBackend;
+    use crate::job::JobOptions;
+    use crate::mapping::MappingTable;
+    use crate::tags::MergePolicy;
+    use std::fs;
+
+    #[test]
+    fn stamp_changes_with_the_mapping_and_merge_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let nfo = dir.path().join("movie.nfo");
+        fs::write(&nfo, "<movie><title>Heat</title></movie>").unwrap();
+        let stamp = |backend: &MkvBackend, options: &JobOptions| {
+            let job = Job::resolve(&dir.path().join("movie.mkv"), Some(&nfo), None, options);
+            stamp(&[nfo.as_path()], None, &settings(backend, &job)).unwrap()
+        };
+        let options = JobOptions::default();
+        let base = stamp(&MkvBackend::default(), &options);
+        assert_eq!(base, stamp(&MkvBackend::default(), &options));
+
+        let legacy = MkvBackend::with_mapping(MappingTable::preset("mkv", Some("legacy")).unwrap());
+        assert_ne!(base, stamp(&legacy, &options));
+
+        let merging = JobOptions { merge: Some(MergePolicy::FillMissing), ..JobOptions::default() };
+        assert_ne!(base, stamp(&MkvBackend::default(), &merging));
+    }
+}
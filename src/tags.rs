@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+/// A single container tag, named the way the container expects it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub value: String,
+}
+
+/// An ordered list of tags for one file. Names may repeat (e.g. one GENRE per genre).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct TagSet {
+    pub tags: Vec<Tag>,
+}
+
+impl TagSet {
+    pub fn new() -> Self {
+        TagSet::default()
+    }
+
+    /// Adds a tag, skipping empty values.
+    pub fn push(&mut self, name: &str, value: &str) {
+        if !value.trim().is_empty() {
+            self.tags.push(Tag { name: name.to_string(), value: value.to_string() });
+        }
+    }
+
+    pub fn push_all<'a, I: IntoIterator<Item = &'a String>>(&mut self, name: &str, values: I) {
+        for value in values {
+            self.push(name, value);
+        }
+    }
+
+    /// First value for `name`, compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|t| t.name.eq_ignore_ascii_case(name)).map(|t| t.value.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.tags.iter().filter(|t| t.name.eq_ignore_ascii_case(name)).map(|t| t.value.as_str()).collect()
+    }
+
+    /// A copy without any tags named `name`, compared case-insensitively.
+    pub fn without(&self, name: &str) -> TagSet {
+        TagSet { tags: self.tags.iter().filter(|t| !t.name.eq_ignore_ascii_case(name)).cloned().collect() }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Tag> {
+        self.tags.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+}
+
+/// How NFO tags are combined with the ones already in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergePolicy {
+    /// NFO values replace tags of the same name; everything else is kept.
+    NfoWins,
+    /// Tags already in the file win; the NFO adds names the file lacks.
+    ExistingWins,
+    /// Like `ExistingWins`, but cover art already in the file is kept too.
+    FillMissing,
+}
+
+impl MergePolicy {
+    pub const NAMES: [&'static str; 3] = ["nfo-wins", "existing-wins", "fill-missing"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MergePolicy::NfoWins => "nfo-wins",
+            MergePolicy::ExistingWins => "existing-wins",
+            MergePolicy::FillMissing => "fill-missing",
+        }
+    }
+
+    /// Whether a supplied cover replaces one already embedded.
+    pub fn replaces_cover(&self) -> bool {
+        *self != MergePolicy::FillMissing
+    }
+
+    pub fn merge(&self, existing: &TagSet, nfo: &TagSet) -> TagSet {
+        let tags = self.merge_keyed(existing.tags.clone(), nfo.tags.clone(), |t| t.name.to_ascii_lowercase());
+        TagSet { tags }
+    }
+
+    /// Merges two lists of entries whose names may repeat. Names are compared
+    /// with `key`, and a name is taken whole from one side or the other.
+    pub fn merge_keyed<T, K: PartialEq>(&self, existing: Vec<T>, nfo: Vec<T>, key: impl Fn(&T) -> K) -> Vec<T> {
+        let (winner, loser) = match self {
+            MergePolicy::NfoWins => (nfo, existing),
+            MergePolicy::ExistingWins | MergePolicy::FillMissing => (existing, nfo),
+        };
+        let taken: Vec<K> = winner.iter().map(&key).collect();
+        let mut merged = winner;
+        merged.extend(loser.into_iter().filter(|entry| !taken.contains(&key(entry))));
+        merged
+    }
+}
+
+impl std::fmt::Display for MergePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfo-wins" => Ok(MergePolicy::NfoWins),
+            "existing-wins" => Ok(MergePolicy::ExistingWins),
+            "fill-missing" => Ok(MergePolicy::FillMissing),
+            _ => Err(format!("unknown merge policy {}, expected one of {}", s, MergePolicy::NAMES.join(", "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(entries: &[(&'static str, i32)]) -> Vec<(&'static str, i32)> {
+        entries.to_vec()
+    }
+
+    #[test]
+    fn merge_keyed_takes_each_name_whole_from_one_side() {
+        let existing = pairs(&[("genre", 1), ("genre", 2), ("comment", 3)]);
+        let nfo = pairs(&[("title", 10), ("genre", 11)]);
+        let merge = |policy: MergePolicy| policy.merge_keyed(existing.clone(), nfo.clone(), |(name, _)| *name);
+
+        assert_eq!(merge(MergePolicy::NfoWins), [("title", 10), ("genre", 11), ("comment", 3)]);
+        let kept = [("genre", 1), ("genre", 2), ("comment", 3), ("title", 10)];
+        assert_eq!(merge(MergePolicy::ExistingWins), kept);
+        assert_eq!(merge(MergePolicy::FillMissing), kept);
+    }
+
+    #[test]
+    fn merge_keyed_with_an_empty_side() {
+        let entries = pairs(&[("a", 1), ("a", 2)]);
+        for policy in [MergePolicy::NfoWins, MergePolicy::ExistingWins, MergePolicy::FillMissing] {
+            assert_eq!(policy.merge_keyed(entries.clone(), Vec::new(), |(name, _)| *name), entries);
+            assert_eq!(policy.merge_keyed(Vec::new(), entries.clone(), |(name, _)| *name), entries);
+        }
+    }
+
+    #[test]
+    fn merge_compares_tag_names_ignoring_case() {
+        let mut existing = TagSet::new();
+        existing.push("Title", "Old");
+        existing.push("COMMENT", "ripped");
+        let mut nfo = TagSet::new();
+        nfo.push("TITLE", "New");
+        let merged = MergePolicy::NfoWins.merge(&existing, &nfo);
+        assert_eq!(merged.get_all("title"), ["New"]);
+        assert_eq!(merged.get("comment"), Some("ripped"));
+        let merged = MergePolicy::ExistingWins.merge(&existing, &nfo);
+        assert_eq!(merged.get_all("title"), ["Old"]);
+    }
+
+    #[test]
+    fn only_fill_missing_keeps_the_cover() {
+        assert!(MergePolicy::NfoWins.replaces_cover());
+        assert!(MergePolicy::ExistingWins.replaces_cover());
+        assert!(!MergePolicy::FillMissing.replaces_cover());
+    }
+}
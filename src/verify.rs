@@ -0,0 +1,180 @@
+use crate::backend::{ContainerBackend, MediaSummary};
+use crate::diff::{compare, Change};
+use crate::job::Job;
+use crate::tags::{MergePolicy, TagSet};
+use std::io::{self, Error};
+use std::path::Path;
+
+/// Re-reads the tagged file at `path` and checks it against its source and
+/// the tags that were meant to be written, if any. Returns every problem
+/// found in one error.
+pub fn verify(
+    backend: &dyn ContainerBackend,
+    job: &Job,
+    path: &Path,
+    source: &MediaSummary,
+    tags: Option<&TagSet>,
+    cover_expected: bool,
+) -> io::Result<()> {
+    let mut problems = Vec::new();
+
+    let result = backend.summarize(path)?;
+    if result.streams != source.streams {
+        problems.push(format!("streams changed from [{}] to [{}]", source.streams.join(", "), result.streams.join(", ")));
+    }
+    match (source.duration, result.duration) {
+        (Some(before), Some(after)) if (before - after).abs() > 0.01 => {
+            problems.push(format!("duration changed from {:.2}s to {:.2}s", before, after));
+        }
+        (Some(_), None) => problems.push("duration is missing".to_string()),
+        _ => {}
+    }
+
+    if let Some(tags) = tags {
+        let written = backend.read_tags(path)?;
+        for field in compare(&written, tags) {
+            let wrong = match (job.merge, field.change) {
+                (_, Change::Unchanged) => false,
+                (None, _) => true,
+                (Some(_), Change::Added) => true,
+                (Some(MergePolicy::NfoWins), Change::Changed) => true,
+                _ => false,
+            };
+            if wrong {
+                problems.push(format!("tag {} reads back as [{}], expected [{}]",
+                    field.name, field.current.join(", "), field.proposed.join(", ")));
+            }
+        }
+    }
+
+    if cover_expected && backend.read_cover(path)?.is_none() {
+        problems.push("cover art is missing".to_string());
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("Verification of {} failed: {}", path.display(), problems.join("; "))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cover::{Cover, EmbeddedCover};
+    use crate::job::JobOptions;
+    use crate::nfo::Nfo;
+    use std::path::PathBuf;
+
+    /// A backend whose "file" reads back as the fields here.
+    struct ReadBack {
+        tags: TagSet,
+        summary: MediaSummary,
+        cover: bool,
+    }
+
+    impl ContainerBackend for ReadBack {
+        fn name(&self) -> &'static str {
+            "read-back"
+        }
+
+        fn probe(&self, _path: &Path, _header: &[u8]) -> bool {
+            true
+        }
+
+        fn tags_for(&self, _nfo: &Nfo) -> TagSet {
+            TagSet::new()
+        }
+
+        fn nfo_from_tags(&self, _tags: &TagSet) -> Nfo {
+            Nfo::default()
+        }
+
+        fn read_tags(&self, _path: &Path) -> io::Result<TagSet> {
+            Ok(self.tags.clone())
+        }
+
+        fn summarize(&self, _path: &Path) -> io::Result<MediaSummary> {
+            Ok(self.summary.clone())
+        }
+
+        fn read_cover(&self, _path: &Path) -> io::Result<Option<EmbeddedCover>> {
+            Ok(self.cover.then(|| EmbeddedCover::from_bytes(b"\xFF\xD8\xFF".to_vec())))
+        }
+
+        fn write_tags(&self, _job: &Job, _tags: &TagSet) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn attach_cover(&self, _job: &Job, _cover: &Cover) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn strip(&self, _job: &Job) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> TagSet {
+        let mut tags = TagSet::new();
+        for (name, value) in pairs {
+            tags.push(name, value);
+        }
+        tags
+    }
+
+    fn source() -> MediaSummary {
+        MediaSummary { streams: vec!["video:V_TEST".to_string()], duration: Some(60.0) }
+    }
+
+    /// Checks a file that reads back as TITLE=Old and COMMENT=kept against
+    /// an NFO that asked for TITLE=New and GENRE=Crime.
+    fn check(merge: Option<MergePolicy>, written: &[(&str, &str)]) -> io::Result<()> {
+        let backend = ReadBack { tags: tags(written), summary: source(), cover: false };
+        let video = PathBuf::from("video.mkv");
+        let job = Job::resolve(&video, None, None, &JobOptions { merge, ..JobOptions::default() });
+        verify(&backend, &job, &video, &source(), Some(&tags(&[("TITLE", "New"), ("GENRE", "Crime")])), false)
+    }
+
+    #[test]
+    fn without_merge_the_tags_must_match_exactly() {
+        assert!(check(None, &[("TITLE", "New"), ("GENRE", "Crime")]).is_ok());
+        let error = check(None, &[("TITLE", "New"), ("GENRE", "Crime"), ("COMMENT", "kept")]).unwrap_err();
+        assert!(error.to_string().contains("tag COMMENT reads back as [kept], expected []"));
+        assert!(check(None, &[("TITLE", "Old"), ("GENRE", "Crime")]).is_err());
+    }
+
+    #[test]
+    fn nfo_wins_allows_kept_tags_but_not_old_values() {
+        assert!(check(Some(MergePolicy::NfoWins), &[("TITLE", "New"), ("GENRE", "Crime"), ("COMMENT", "kept")]).is_ok());
+        assert!(check(Some(MergePolicy::NfoWins), &[("TITLE", "Old"), ("GENRE", "Crime")]).is_err());
+        assert!(check(Some(MergePolicy::NfoWins), &[("TITLE", "New")]).is_err());
+    }
+
+    #[test]
+    fn existing_wins_and_fill_missing_allow_old_values_but_not_missing_names() {
+        for policy in [MergePolicy::ExistingWins, MergePolicy::FillMissing] {
+            assert!(check(Some(policy), &[("TITLE", "Old"), ("GENRE", "Crime"), ("COMMENT", "kept")]).is_ok());
+            let error = check(Some(policy), &[("TITLE", "Old")]).unwrap_err();
+            assert!(error.to_string().contains("tag GENRE reads back as [], expected [Crime]"));
+        }
+    }
+
+    #[test]
+    fn reports_changed_streams_and_missing_cover_together() {
+        let backend = ReadBack {
+            tags: TagSet::new(),
+            summary: MediaSummary { streams: Vec::new(), duration: None },
+            cover: false,
+        };
+        let video = PathBuf::from("video.mkv");
+        let job = Job::resolve(&video, None, None, &JobOptions::default());
+        let error = verify(&backend, &job, &video, &source(), None, true).unwrap_err().to_string();
+        assert!(error.contains("streams changed from [video:V_TEST] to []"));
+        assert!(error.contains("duration is missing"));
+        assert!(error.contains("cover art is missing"));
+
+        let backend = ReadBack { cover: true, summary: source(), ..backend };
+        assert!(verify(&backend, &job, &video, &source(), None, true).is_ok());
+    }
+}